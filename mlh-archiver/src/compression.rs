@@ -0,0 +1,367 @@
+//! Compressed article files.
+//!
+//! An article is stored as `{n}.eml`, `{n}.eml.zst` or `{n}.eml.gz`. The compression
+//! is always taken from the file name, so the three can be mixed in a group folder.
+
+use crate::config::Compression;
+use crate::file_utils;
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const ZSTD_LEVEL: i32 = 9;
+pub(crate) const ALL: [Compression; 3] = [Compression::None, Compression::Zstd, Compression::Gzip];
+
+impl Compression {
+    /// suffix added to the file name, after `.eml`
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Zstd => ".zst",
+            Compression::Gzip => ".gz",
+        }
+    }
+
+    /// compression of a file, from its name
+    pub fn of_path(path: &Path) -> Compression {
+        let name = path.to_string_lossy();
+        ALL.into_iter()
+            .filter(|c| *c != Compression::None)
+            .find(|c| name.ends_with(c.extension()))
+            .unwrap_or(Compression::None)
+    }
+
+    pub fn compress(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(content.to_vec()),
+            Compression::Zstd => zstd::encode_all(content, ZSTD_LEVEL),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    pub fn decompress(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = vec![];
+        self.reader(content).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    fn reader<'a, R: Read + 'a>(&self, inner: R) -> Box<dyn Read + 'a> {
+        match self {
+            Compression::None => Box::new(inner),
+            Compression::Zstd => match zstd::Decoder::new(inner) {
+                Ok(decoder) => Box::new(decoder),
+                Err(e) => Box::new(FailingReader(Some(e))),
+            },
+            Compression::Gzip => Box::new(MultiGzDecoder::new(inner)),
+        }
+    }
+}
+
+/// reports an error on the first read
+struct FailingReader(Option<io::Error>);
+
+impl Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(self
+            .0
+            .take()
+            .unwrap_or_else(|| io::Error::other("reader failed")))
+    }
+}
+
+/// `{article_number}.eml`, with the extension of the compression
+pub fn article_file_name(article_number: usize, compression: Compression) -> String {
+    format!("{article_number}.eml{}", compression.extension())
+}
+
+/// article number and compression of an article file name
+pub fn parse_article_file_name(name: &str) -> Option<(usize, Compression)> {
+    ALL.into_iter().find_map(|compression| {
+        let number = name
+            .strip_suffix(compression.extension())?
+            .strip_suffix(".eml")?
+            .parse::<usize>()
+            .ok()?;
+        Some((number, compression))
+    })
+}
+
+/// path of a stored article in a group folder, whatever its compression
+pub fn find_article_file(group_path: &Path, article_number: usize) -> Option<PathBuf> {
+    ALL.iter()
+        .map(|compression| group_path.join(article_file_name(article_number, *compression)))
+        .find(|path| path.is_file())
+}
+
+/// the path itself if it exists, otherwise the same article with another compression
+pub fn resolve_article_path(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    let (article_number, _) = parse_article_file_name(path.file_name()?.to_str()?)?;
+    find_article_file(path.parent()?, article_number)
+}
+
+/// buffered reader of a file, decompressing it if needed
+pub fn open_reader(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    Ok(Box::new(BufReader::new(
+        Compression::of_path(path).reader(file),
+    )))
+}
+
+/// checks that an article file was completely written: it can be read (and decompressed),
+/// has a header section ended by an empty line, and its last line is complete
+pub fn article_file_is_complete(path: &Path) -> bool {
+    let Ok(mut reader) = open_reader(path) else {
+        return false;
+    };
+    let mut line = vec![];
+    let mut has_headers = false;
+    while !has_headers {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return false,
+            Ok(_) => has_headers = line == b"\n" || line == b"\r\n",
+        }
+    }
+
+    // plain files only need their last byte checked
+    if Compression::of_path(path) == Compression::None {
+        return File::open(path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::End(-1))?;
+                let mut last = [0u8];
+                file.read_exact(&mut last)?;
+                Ok(last[0] == b'\n')
+            })
+            .unwrap_or(false);
+    }
+    let mut rest = vec![];
+    match reader.read_to_end(&mut rest) {
+        // nothing after the headers is an empty body
+        Ok(_) => rest.last().is_none_or(|last| *last == b'\n'),
+        Err(_) => false,
+    }
+}
+
+/// Counters of a recompression
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecompressStats {
+    pub recompressed: usize,
+    pub unchanged: usize,
+    pub failed: usize,
+}
+
+/// new path of the articles already rewritten, by device and inode. Shared by the
+/// groups of a recompression, to keep the hard links between them
+pub type Rewritten = HashMap<(u64, u64), PathBuf>;
+
+/// rewrites every article of a group folder with the given compression
+///
+/// Each file is decompressed and checked before the new file is written (to a
+/// temporary name, then renamed), and the old file is only removed afterwards.
+/// Interrupting it leaves, at worst, both versions of an article, which the next
+/// run cleans up. Hard linked articles (duplicates) stay hard linked, in the group
+/// and with the groups rewritten before with the same `rewritten`.
+pub fn recompress_group(
+    group_path: &Path,
+    compression: Compression,
+    rewritten: &mut Rewritten,
+) -> io::Result<RecompressStats> {
+    let mut stats = RecompressStats::default();
+
+    let mut articles: Vec<(usize, Compression, PathBuf)> = fs::read_dir(group_path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            let (number, current) = parse_article_file_name(path.file_name()?.to_str()?)?;
+            Some((number, current, path))
+        })
+        .collect();
+    articles.sort_by_key(|(number, _, _)| *number);
+
+    for (article_number, current, path) in articles {
+        let target = group_path.join(article_file_name(article_number, compression));
+        if current == compression {
+            stats.unchanged += 1;
+            continue;
+        }
+        if target.is_file() {
+            // a previous run was interrupted after the rename
+            log::debug!("{} already recompressed", path.display());
+            fs::remove_file(&path)?;
+            stats.recompressed += 1;
+            continue;
+        }
+
+        let inode = file_id(&path)?;
+        if let Some(linked) = inode.and_then(|(id, _)| rewritten.get(&id)) {
+            fs::hard_link(linked, &target)?;
+            fs::remove_file(&path)?;
+            stats.recompressed += 1;
+            continue;
+        }
+
+        let content = match fs::read(&path).and_then(|c| current.decompress(&c)) {
+            Ok(content) => content,
+            Err(e) => {
+                log::error!("could not read {}, keeping it: {e}", path.display());
+                stats.failed += 1;
+                continue;
+            }
+        };
+        let compressed = compression.compress(&content)?;
+        if compression.decompress(&compressed)? != content {
+            log::error!(
+                "{} does not survive recompression, keeping it",
+                path.display()
+            );
+            stats.failed += 1;
+            continue;
+        }
+
+        file_utils::write_atomic(&target, &compressed)?;
+        fs::remove_file(&path)?;
+        if let Some((id, true)) = inode {
+            rewritten.insert(id, target);
+        }
+        stats.recompressed += 1;
+    }
+    Ok(stats)
+}
+
+/// device and inode of a file, and whether other hard links point to it
+#[cfg(unix)]
+fn file_id(path: &Path) -> io::Result<Option<((u64, u64), bool)>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path)?;
+    Ok(Some((
+        (metadata.dev(), metadata.ino()),
+        metadata.nlink() > 1,
+    )))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> io::Result<Option<((u64, u64), bool)>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_article_file_names() {
+        assert_eq!(article_file_name(3, Compression::Zstd), "3.eml.zst");
+        assert_eq!(
+            parse_article_file_name("3.eml.gz"),
+            Some((3, Compression::Gzip))
+        );
+        assert_eq!(
+            parse_article_file_name("3.eml"),
+            Some((3, Compression::None))
+        );
+        assert_eq!(parse_article_file_name("3.eml.tmp"), None);
+        assert_eq!(parse_article_file_name("__errors"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let content = b"Subject: x\r\n\r\nbody\r\n";
+        for compression in ALL {
+            let compressed = compression.compress(content).unwrap();
+            assert_eq!(compression.decompress(&compressed).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn test_article_file_is_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let check = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            let compressed = Compression::of_path(&path).compress(content).unwrap();
+            fs::write(&path, compressed).unwrap();
+            article_file_is_complete(&path)
+        };
+        assert!(check("1.eml", b"Subject: x\r\n\r\nbody\r\n"));
+        assert!(check("2.eml.zst", b"Subject: x\r\n\r\nbody\r\n"));
+        assert!(check("3.eml.gz", b"Subject: x\n\n"));
+        // cut in the headers, or in the middle of a line
+        assert!(!check("4.eml", b"Subject: x\r\nFrom: a\r\n"));
+        assert!(!check("5.eml", b"Subject: x\r\n\r\nbo"));
+        assert!(!check("6.eml.zst", b"Subject: x\r\n\r\nbo"));
+        assert!(!check("7.eml", b""));
+        fs::write(dir.path().join("8.eml.zst"), b"garbage").unwrap();
+        assert!(!article_file_is_complete(&dir.path().join("8.eml.zst")));
+    }
+
+    #[test]
+    fn test_recompress_group() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("1.eml"), "one\n").unwrap();
+        fs::write(
+            dir.path().join("2.eml.gz"),
+            Compression::Gzip.compress(b"two\n").unwrap(),
+        )
+        .unwrap();
+        fs::hard_link(dir.path().join("1.eml"), dir.path().join("3.eml")).unwrap();
+        fs::write(dir.path().join("4.eml.gz"), "not gzip").unwrap();
+
+        let stats = recompress_group(dir.path(), Compression::Zstd, &mut Rewritten::new()).unwrap();
+        assert_eq!(
+            stats,
+            RecompressStats {
+                recompressed: 3,
+                unchanged: 0,
+                failed: 1
+            }
+        );
+        assert!(!dir.path().join("1.eml").exists());
+        assert!(dir.path().join("4.eml.gz").exists());
+        let mut two = String::new();
+        open_reader(&dir.path().join("2.eml.zst"))
+            .unwrap()
+            .read_to_string(&mut two)
+            .unwrap();
+        assert_eq!(two, "two\n");
+        assert_eq!(
+            find_article_file(dir.path(), 3),
+            Some(dir.path().join("3.eml.zst"))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            file_id(&dir.path().join("3.eml.zst"))
+                .unwrap()
+                .map(|(_, shared)| shared),
+            Some(true)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recompress_keeps_links_between_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let (foo, bar) = (dir.path().join("foo"), dir.path().join("bar"));
+        fs::create_dir(&foo).unwrap();
+        fs::create_dir(&bar).unwrap();
+        fs::write(foo.join("1.eml"), "shared\n").unwrap();
+        fs::hard_link(foo.join("1.eml"), bar.join("7.eml")).unwrap();
+
+        let mut rewritten = Rewritten::new();
+        for group in [&foo, &bar] {
+            let stats = recompress_group(group, Compression::Zstd, &mut rewritten).unwrap();
+            assert_eq!(stats.recompressed, 1);
+        }
+        let id = |path: PathBuf| file_id(&path).unwrap().unwrap();
+        assert_eq!(id(foo.join("1.eml.zst")), id(bar.join("7.eml.zst")));
+        assert!(id(foo.join("1.eml.zst")).1);
+    }
+}
@@ -1,22 +1,143 @@
 use crate::{errors::ConfigError, file_utils, range_inputs};
-use clap::{Args, Parser, ValueHint};
-use config::Config;
+use chrono::NaiveDate;
+use clap::{
+    Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, ValueHint,
+    parser::{ArgMatches, ValueSource},
+};
+use config::{Config, Environment, Source};
 use glob::glob;
-use inquire::MultiSelect;
+use inquire::{InquireError, MultiSelect};
+use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 // TODO: test use confique::Config;
 
-#[derive(Debug, Parser, Default, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+/// Track mailing lists over NNTP into local files
+#[derive(Debug, Parser, PartialEq, Eq)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// without a command, the archiver runs `sync`
+    #[command(flatten)]
+    sync: ConfigArgs,
+}
+
+/// The configuration options, accepted by every command
+#[derive(Debug, Args, Clone, Default, PartialEq, Eq)]
+pub struct ConfigArgs {
     // config file location override
     #[arg(short, long, default_value = "nntp_config*", value_hint = ValueHint::FilePath)]
     config_file: String,
+    /// Print the configuration merged from every source, and the options each source set, then exit
+    #[arg(long)]
+    print_config: bool,
 
-    #[clap(flatten)]
+    #[command(flatten)]
     app_config: Option<AppConfig>,
 }
 
+#[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Keep the selected groups up-to-date with the server (the default)
+    Sync(ConfigArgs),
+    /// Fetch a range of articles of one group, and exit
+    Fetch(FetchArgs),
+    /// Fetch the articles listed by the server (LISTGROUP) that are missing up to the read status of each selected group, and exit
+    Backfill(ConfigArgs),
+    /// List the groups available in the server, with their article counts, and exit
+    Groups(ConfigArgs),
+    /// Summarize the groups archived in output_dir, and exit
+    Status(StatusArgs),
+    /// Check the groups archived in output_dir for missing and damaged articles, and exit
+    Verify(VerifyArgs),
+    /// Write the groups archived in output_dir as mboxrd files, and exit
+    ExportMbox(ExportMboxArgs),
+    /// Rewrite the articles in output_dir with the configured compression, and exit
+    Recompress(RecompressArgs),
+    /// Rebuild the Message-ID index from the articles in output_dir, and exit
+    RebuildMessageIndex(ConfigArgs),
+}
+
+impl Command {
+    fn config_args(&self) -> &ConfigArgs {
+        match self {
+            Command::Sync(config)
+            | Command::Backfill(config)
+            | Command::Groups(config)
+            | Command::RebuildMessageIndex(config) => config,
+            Command::Fetch(args) => &args.config,
+            Command::Status(args) => &args.config,
+            Command::Verify(args) => &args.config,
+            Command::ExportMbox(args) => &args.config,
+            Command::Recompress(args) => &args.config,
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct FetchArgs {
+    /// Group to read from
+    pub group: String,
+    /// Articles to fetch. Comma separated values, or dash separated ranges, like low-high
+    #[arg(value_parser = parse_article_range)]
+    pub range: String,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+fn parse_article_range(range: &str) -> Result<String, range_inputs::SequenceParseError> {
+    range_inputs::parse_sequence(range).map(|_| range.to_string())
+}
+
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct StatusArgs {
+    /// Groups to summarize. Defaults to every group in output_dir
+    pub groups: Vec<String>,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct ExportMboxArgs {
+    /// Groups to export. Defaults to every group in output_dir
+    pub groups: Vec<String>,
+    /// Where the mbox files are written
+    #[arg(short, long, default_value = "./mbox", value_hint = ValueHint::DirPath)]
+    pub destination: String,
+    /// Write one mbox per month ({group}/{YYYY-MM}.mbox) based on the Date header, instead of {group}.mbox
+    #[arg(long)]
+    pub split_by_month: bool,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct RecompressArgs {
+    /// Groups to recompress. Defaults to every group in output_dir
+    pub groups: Vec<String>,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Args, Clone, PartialEq, Eq)]
+pub struct VerifyArgs {
+    /// Groups to check. Defaults to every group in output_dir
+    pub groups: Vec<String>,
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Also compare with the article numbers listed by the server (LISTGROUP)
+    #[arg(long)]
+    pub server: bool,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
 #[derive(Debug, Args, Default, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 pub struct AppConfig {
     /// nntp server domain/ip
@@ -31,53 +152,470 @@ pub struct AppConfig {
     /// Number of worker threads connecting to different lists
     #[arg(short, long, default_value = "1")]
     pub nthreads: u8,
+    /// ARTICLE commands kept in flight on each connection (at most 16). 1 disables pipelining
+    #[arg(long, default_value = "1")]
+    pub pipeline_depth: u8,
+    /// Layout of each group: "eml" ({number}.eml files), "maildir" (readable by mutt/notmuch), "public-inbox" (v2 git repositories) or "sqlite" (archive.sqlite3 database)
+    #[arg(long, value_enum, default_value = "eml")]
+    pub output_format: OutputFormat,
+    /// Compression of the stored .eml files: "none", "zstd" ({number}.eml.zst) or "gzip" ({number}.eml.gz)
+    #[arg(long, value_enum, default_value = "none")]
+    pub compression: Compression,
+    /// How an article already stored in another group is saved: "copy" (full file), "hardlink", or "manifest" (listed in the group's __duplicates file)
+    #[arg(long, value_enum, default_value = "copy")]
+    pub duplicates: DuplicateMode,
+    /// Days during which articles the server could not provide are tried again, less and less often. 0 disables the retries
+    #[arg(long, default_value = "30")]
+    pub error_retry_max_age_days: u32,
+    /// (optional) Also append each new article to {dir}/{group}/{YYYY-MM}.mbox (mboxrd), as it is fetched
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub mbox_live_dir: Option<String>,
     /// If true, the app will keep running forever. Otherwise, stop after reading all groups
     #[arg(short, long, default_value = "true")]
     pub loop_groups: bool,
 
     /// List of groups to be read. "ALL" will select all lists available.
+    /// Glob patterns (linux.kernel.*) and regular expressions (re:^linux\.) select the matching groups.
     /// Empty value will prompt a selection in the TUI (and save selected values)
     #[arg(long)]
-    pub group_lists: Option<Vec<String>>,
-    ///  (optional). Read a specific range of articles from the first list provided. Comma separated values, or dash separated ranges, like low-high
+    pub group_lists: Option<Vec<GroupEntry>>,
+    /// (optional) Groups never read, even if selected. Names, glob patterns or regular expressions (re:...)
+    #[arg(long)]
+    pub exclude_groups: Option<Vec<String>>,
+    /// Seconds between checks for groups created on the server (NEWGROUPS), read if group_lists selects them. 0 disables the checks
+    #[arg(long, default_value = "3600")]
+    pub discover_groups_interval: u64,
+    /// Fail instead of prompting for the groups when group_lists is empty. Always the case when stdin is not a terminal
+    #[arg(long)]
+    pub non_interactive: bool,
+    /// (optional) Articles fetched per second, by all the connections together
+    #[arg(long)]
+    pub max_articles_per_second: Option<u32>,
+    /// (optional) Bytes of articles fetched per second, by all the connections together
+    #[arg(long)]
+    pub max_bytes_per_second: Option<u64>,
+    /// (optional) Articles fetched per second, by each connection
     #[arg(long)]
-    pub article_range: Option<String>,
+    pub connection_max_articles_per_second: Option<u32>,
+    /// (optional) Bytes of articles fetched per second, by each connection
+    #[arg(long)]
+    pub connection_max_bytes_per_second: Option<u64>,
+    /// Seconds every connection waits when the server answers 400 or 502 (too many connections), doubled while it keeps refusing
+    #[arg(long, default_value = "60")]
+    pub server_busy_backoff: u64,
+    /// Shortest time in seconds between two checks of a group, for the busiest groups
+    #[arg(long, default_value = "300")]
+    pub poll_interval_min: u64,
+    /// Longest time in seconds between two checks of a group, for the groups without new articles
+    #[arg(long, default_value = "21600")]
+    pub poll_interval_max: u64,
+    /// Seconds to wait for the workers to finish their current article when stopping
+    #[arg(long, default_value = "30")]
+    pub shutdown_timeout: u64,
+
+    /// How the connection is secured: "none", "implicit" (NNTPS, usually port 563) or "starttls"
+    #[arg(long, value_enum, default_value = "none")]
+    pub tls_mode: TlsMode,
+    /// (optional) PEM bundle with the CA certificates to trust. Defaults to the Mozilla root store
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub tls_ca_bundle: Option<String>,
+    /// (optional) PEM client certificate (chain) presented to the server
+    #[arg(long, value_hint = ValueHint::FilePath, requires = "tls_client_key")]
+    pub tls_client_cert: Option<String>,
+    /// (optional) PEM private key of the client certificate
+    #[arg(long, value_hint = ValueHint::FilePath, requires = "tls_client_cert")]
+    pub tls_client_key: Option<String>,
+
+    /// (optional) user name, for servers that require authentication
+    #[arg(short, long)]
+    pub username: Option<String>,
+    /// (optional) file containing the password. Takes precedence over password_env
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub password_file: Option<String>,
+    /// (optional) environment variable containing the password. Defaults to NNTP_PASSWORD
+    #[arg(long)]
+    pub password_env: Option<String>,
+    /// Authentication method: "user-pass" (AUTHINFO USER/PASS) or "sasl-plain" (AUTHINFO SASL PLAIN)
+    #[arg(long, value_enum, default_value = "user-pass")]
+    pub auth_mechanism: AuthMechanism,
 }
 
-pub fn read_config() -> Result<AppConfig, anyhow::Error> {
-    let opts = Opts::parse();
+#[derive(
+    Debug, Clone, Copy, Default, ValueEnum, serde::Deserialize, serde::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// plain text connection
+    #[default]
+    None,
+    /// TLS from the first byte (NNTPS)
+    Implicit,
+    /// plain text connection upgraded with the STARTTLS command
+    Starttls,
+}
 
-    let base_config = opts.app_config.unwrap_or_default();
+/// An entry of group_lists: the name of a group, or a group with its own settings
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum GroupEntry {
+    Name(String),
+    Settings(GroupConfig),
+}
 
-    let defaults = Config::try_from(&base_config).unwrap();
+impl FromStr for GroupEntry {
+    type Err = std::convert::Infallible;
 
-    // TODO: config layering is not working properly
-    let config = Config::builder()
-        .set_default("port", 119)
-        .unwrap()
-        .add_source(defaults)
-        // env variable config
-        .add_source(
-            config::Environment::with_prefix("NNTP")
-                .try_parsing(true)
-                .separator("_"),
-        )
-        // TODO:  add xdg_home config
-        .add_source(
-            glob(&opts.config_file)?
-                .map(|path| config::File::from(path.unwrap()))
-                .collect::<Vec<_>>(),
-        );
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(GroupEntry::Name(name.to_string()))
+    }
+}
 
-    let config = config.build().unwrap();
+impl GroupEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            GroupEntry::Name(name) => name,
+            GroupEntry::Settings(group) => &group.name,
+        }
+    }
 
-    let app_config: AppConfig = config.try_deserialize()?;
+    pub fn into_config(self) -> GroupConfig {
+        match self {
+            GroupEntry::Name(name) => GroupConfig::new(&name),
+            GroupEntry::Settings(group) => group,
+        }
+    }
+}
 
-    Ok(app_config)
+/// A group to read, and its own settings
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    pub name: String,
+    /// (optional) articles to fetch once, instead of following the group. Same format as the fetch command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    /// (optional) where to start reading the group, if it was never read: an article number, or a date (YYYY-MM-DD)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_from: Option<StartFrom>,
+    /// (optional) fixed seconds between checks of the group, instead of adapting to its activity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+    /// groups due at the same time are checked by descending priority. Defaults to 0
+    #[serde(default)]
+    pub priority: i32,
+    /// (optional) layout of the group, instead of output_format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+}
+
+impl GroupConfig {
+    /// a group with the global settings
+    pub fn new(name: &str) -> GroupConfig {
+        GroupConfig {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// What an entry of group_lists or exclude_groups matches
+#[derive(Debug, Clone)]
+pub enum GroupPattern {
+    Name(String),
+    /// "ALL"
+    All,
+    /// a name with `*`, `?` or `[...]`
+    Glob(glob::Pattern),
+    /// "re:" followed by a regular expression, matched anywhere in the name
+    Regex(Regex),
+}
+
+impl GroupPattern {
+    pub fn parse(pattern: &str) -> Result<GroupPattern, ConfigError> {
+        let invalid = |error: String| ConfigError::InvalidGroupPattern {
+            pattern: pattern.to_string(),
+            error,
+        };
+        if pattern == "ALL" {
+            Ok(GroupPattern::All)
+        } else if let Some(regex) = pattern.strip_prefix("re:") {
+            Regex::new(regex)
+                .map(GroupPattern::Regex)
+                .map_err(|e| invalid(e.to_string()))
+        } else if pattern.contains(['*', '?', '[']) {
+            glob::Pattern::new(pattern)
+                .map(GroupPattern::Glob)
+                .map_err(|e| invalid(e.to_string()))
+        } else {
+            Ok(GroupPattern::Name(pattern.to_string()))
+        }
+    }
+
+    pub fn matches(&self, group: &str) -> bool {
+        match self {
+            GroupPattern::Name(name) => name == group,
+            GroupPattern::All => true,
+            GroupPattern::Glob(pattern) => pattern.matches(group),
+            GroupPattern::Regex(regex) => regex.is_match(group),
+        }
+    }
+
+    pub fn is_name(&self) -> bool {
+        matches!(self, GroupPattern::Name(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum StartFrom {
+    Article(usize),
+    /// first article posted on or after the date
+    Date(NaiveDate),
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    ValueEnum,
+    serde::Deserialize,
+    serde::Serialize,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// one {number}.eml file per article
+    #[default]
+    Eml,
+    /// one Maildir (cur/new/tmp) per group
+    Maildir,
+    /// one public-inbox v2 inbox (git repositories) per group
+    #[serde(rename = "public-inbox")]
+    PublicInbox,
+    /// every group in a single SQLite database
+    Sqlite,
+}
+
+impl OutputFormat {
+    /// folder of the groups configured with this format, when it is not the global one
+    pub fn folder_name(&self) -> &'static str {
+        match self {
+            OutputFormat::Eml => "eml",
+            OutputFormat::Maildir => "maildir",
+            OutputFormat::PublicInbox => "public-inbox",
+            OutputFormat::Sqlite => "sqlite",
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, ValueEnum, serde::Deserialize, serde::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// plain {number}.eml files
+    #[default]
+    None,
+    /// {number}.eml.zst files
+    Zstd,
+    /// {number}.eml.gz files
+    Gzip,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, ValueEnum, serde::Deserialize, serde::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    /// every group gets its own copy of the file
+    #[default]
+    Copy,
+    /// duplicates are hard links to the first stored copy
+    Hardlink,
+    /// duplicates are not written, only listed in the group's __duplicates file
+    Manifest,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, ValueEnum, serde::Deserialize, serde::Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMechanism {
+    /// AUTHINFO USER followed by AUTHINFO PASS
+    #[default]
+    UserPass,
+    /// AUTHINFO SASL with the PLAIN mechanism
+    SaslPlain,
+}
+
+/// name of the folder of the config file in the XDG config directories
+const XDG_CONFIG_FOLDER: &str = "mlh-archiver";
+/// config file names looked for in that folder, the format follows the extension
+const XDG_CONFIG_FILES: [&str; 3] = ["config.yaml", "config.yml", "config.json"];
+
+/// One layer of the configuration, and the options it set
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigSource {
+    pub name: String,
+    pub keys: Vec<String>,
+}
+
+/// parses the command line and merges the configuration of every source.
+/// With --print-config, prints the result and exits
+pub fn read_config() -> Result<(AppConfig, Command), anyhow::Error> {
+    let matches = Opts::command().get_matches();
+    let (app_config, command, sources) = load_config(
+        &matches,
+        std::env::vars().collect(),
+        xdg_config_files(|name| std::env::var(name).ok()),
+    )?;
+
+    if command.config_args().print_config {
+        print!("{}", format_config(&app_config, &sources)?);
+        std::process::exit(0);
+    }
+    Ok((app_config, command))
+}
+
+/// merges the configuration layers, each one overriding the previous ones:
+///
+/// 1. built-in defaults
+/// 2. the XDG config files (`xdg_files`, system ones first)
+/// 3. the files matching --config-file (`nntp_config*` by default)
+/// 4. the `NNTP_*` variables of `env`
+/// 5. the options given on the command line
+pub fn load_config(
+    matches: &ArgMatches,
+    env: HashMap<String, String>,
+    xdg_files: Vec<PathBuf>,
+) -> Result<(AppConfig, Command, Vec<ConfigSource>), anyhow::Error> {
+    let opts = Opts::from_arg_matches(matches)?;
+    // the options are in the matches of the command, if one was given
+    let command_matches = matches.subcommand().map_or(matches, |(_, matches)| matches);
+    let command = opts.command.unwrap_or(Command::Sync(opts.sync));
+    let config_args = command.config_args();
+
+    let mut layers: Vec<(String, Config)> = vec![(
+        "defaults".to_string(),
+        Config::try_from(&AppConfig::defaults())?,
+    )];
+    for path in xdg_files
+        .into_iter()
+        .chain(glob(&config_args.config_file)?.collect::<Result<Vec<_>, _>>()?)
+    {
+        let layer = Config::builder()
+            .add_source(config::File::from(path.as_path()))
+            .build()?;
+        layers.push((path.display().to_string(), layer));
+    }
+
+    let environment = Environment::with_prefix("NNTP")
+        .try_parsing(true)
+        .list_separator(",")
+        .with_list_parse_key("group_lists")
+        .with_list_parse_key("exclude_groups")
+        .source(Some(env.into_iter().collect()));
+    layers.push((
+        "environment".to_string(),
+        Config::builder().add_source(environment).build()?,
+    ));
+
+    // clap fills in its defaults, only what was typed overrides the other layers
+    let mut command_line = Config::builder();
+    if let Some(cli_config) = &config_args.app_config {
+        let cli_config = Config::try_from(cli_config)?.collect()?;
+        for key in AppConfig::keys() {
+            if command_matches.value_source(&key) == Some(ValueSource::CommandLine)
+                && let Some(value) = cli_config.get(&key)
+            {
+                command_line = command_line.set_override(key, value.clone())?;
+            }
+        }
+    }
+    layers.push(("command line".to_string(), command_line.build()?));
+
+    let keys = AppConfig::keys();
+    let mut builder = Config::builder();
+    let mut sources = vec![];
+    for (name, layer) in layers {
+        let mut layer_keys: Vec<String> = layer
+            .collect()?
+            .into_keys()
+            .filter(|key| keys.contains(key))
+            .collect();
+        layer_keys.sort_by_key(|key| keys.iter().position(|k| k == key));
+        sources.push(ConfigSource {
+            name,
+            keys: layer_keys,
+        });
+        builder = builder.add_source(layer);
+    }
+
+    let app_config: AppConfig = builder.build()?.try_deserialize()?;
+    Ok((app_config, command, sources))
+}
+
+/// config files in the XDG config directories, lowest precedence first: the
+/// system ones (`XDG_CONFIG_DIRS`, or /etc/xdg), then the user one
+/// (`XDG_CONFIG_HOME`, or ~/.config)
+pub fn xdg_config_files(env: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    let non_empty = |name| env(name).filter(|value| !value.is_empty());
+
+    let system_dirs = non_empty("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".to_string());
+    // the first directories are the most important ones
+    let mut dirs: Vec<PathBuf> = system_dirs.split(':').rev().map(PathBuf::from).collect();
+    match non_empty("XDG_CONFIG_HOME") {
+        Some(config_home) => dirs.push(PathBuf::from(config_home)),
+        None => dirs.extend(non_empty("HOME").map(|home| Path::new(&home).join(".config"))),
+    }
+
+    dirs.iter()
+        .flat_map(|dir| {
+            XDG_CONFIG_FILES
+                .iter()
+                .map(move |name| dir.join(XDG_CONFIG_FOLDER).join(name))
+        })
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// the merged configuration as YAML, after comments listing what each source set
+pub fn format_config(
+    app_config: &AppConfig,
+    sources: &[ConfigSource],
+) -> Result<String, anyhow::Error> {
+    let mut output = "# sources, each one overriding the previous ones:\n".to_string();
+    for source in sources {
+        let keys = match source.keys.is_empty() {
+            true => "nothing".to_string(),
+            false => source.keys.join(", "),
+        };
+        output += &format!("#   {}: {keys}\n", source.name);
+    }
+    output += &serde_yaml::to_string(app_config)?;
+    Ok(output)
 }
 
 impl AppConfig {
-    /// returns the lists ready to use
+    /// the configuration with the default value of every option
+    pub fn defaults() -> AppConfig {
+        let command = AppConfig::augment_args(clap::Command::new("defaults"));
+        AppConfig::from_arg_matches(&command.get_matches_from(["defaults"]))
+            .expect("options should have a default value or be optional")
+    }
+
+    /// names of the options, as used in config files
+    pub fn keys() -> Vec<String> {
+        AppConfig::augment_args(clap::Command::new("keys"))
+            .get_arguments()
+            .map(|arg| arg.get_id().to_string())
+            .collect()
+    }
+
+    /// returns the lists ready to use, with their settings
     ///
     /// Takes lists from config. If none configured, prompt user for selection.
     /// If list was configured, check if selected lists are available in the server
@@ -85,94 +623,489 @@ impl AppConfig {
     pub fn get_group_lists(
         &mut self,
         list_options: Vec<String>,
-    ) -> Result<Vec<String>, ConfigError> {
-        let mut answer: Vec<String>;
+    ) -> Result<Vec<GroupConfig>, ConfigError> {
+        let answer: Vec<GroupConfig>;
         if self.group_lists.is_none() {
             log::info!("No group_lists defined");
+            if self.non_interactive || !std::io::stdin().is_terminal() {
+                return Err(ConfigError::NoListsNonInteractive);
+            }
 
+            let excluded = self.excluded_groups()?;
+            let list_options: Vec<String> = list_options
+                .into_iter()
+                .filter(|name| !excluded.iter().any(|pattern| pattern.matches(name)))
+                .collect();
             // list of options provides, with "ALL" as first
             let mut select_options = vec!["ALL".to_string()];
             select_options.extend(list_options.clone());
 
-            answer = MultiSelect::new("No groups selected. Select them now:", select_options)
-                .prompt()
-                .unwrap_or_else(|_| std::process::exit(0));
+            let mut selection =
+                match MultiSelect::new("No groups selected. Select them now:", select_options)
+                    .prompt()
+                {
+                    Ok(selection) => selection,
+                    Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => {
+                        return Err(ConfigError::ListSelectionEmpty);
+                    }
+                    Err(e) => return Err(ConfigError::Prompt(e.to_string())),
+                };
 
-            if answer[0] == "ALL" {
+            if selection.first().is_some_and(|first| first == "ALL") {
                 log::info!("All lists selected");
                 log::debug!("Lists selected: {:#?}", list_options);
-                answer = list_options;
+                selection = list_options;
             }
 
-            if answer.is_empty() {
+            if selection.is_empty() {
                 log::info!("empty selection");
                 self.group_lists = None;
                 return Err(ConfigError::ListSelectionEmpty);
             } else {
                 // save selection to a file
                 let mut selected_lists = HashMap::new();
-                selected_lists.insert("group_lists", answer.clone());
+                selected_lists.insert("group_lists", selection.clone());
 
                 match file_utils::write_yaml("nntp_config_selected_lists.yml", &selected_lists) {
                     Ok(_) => Ok(()),
                     Err(e) => Err(ConfigError::Io(e)),
                 }?;
             }
+            answer = selection
+                .iter()
+                .map(|name| GroupConfig::new(name))
+                .collect();
         } else {
-            let mut group_lists = self.group_lists.clone().unwrap();
-
-            // If "ALL" provided, load all lists
-            if group_lists[0] == "ALL" {
-                log::info!("Configured to fetch all lists");
-                log::debug!("Lists selected: {:#?}", list_options);
-                answer = list_options;
-            } else {
-                // or check if lists provided are valid
-                group_lists.dedup();
-                let item_set: HashSet<_> = list_options.iter().collect();
-                group_lists.retain(|item| item_set.contains(item));
-                let (valid, invalid): (Vec<_>, Vec<_>) = group_lists
-                    .into_iter()
-                    .partition(|item| item_set.contains(item));
-
-                if valid.is_empty() {
-                    return Err(ConfigError::AllListsUnavailable);
-                }
-                if !invalid.is_empty() {
-                    log::warn!(
-                        "Some lists are unavailable: {}",
-                        ConfigError::ConfiguredListsNotAvailable {
-                            unavailable_lists: invalid
-                        }
-                    );
-                }
-                answer = valid;
+            let (selected, unavailable) = self.select_configured_groups(&list_options)?;
+            if selected.is_empty() {
+                return Err(ConfigError::AllListsUnavailable);
             }
+            if !unavailable.is_empty() {
+                log::warn!(
+                    "Some lists are unavailable: {}",
+                    ConfigError::ConfiguredListsNotAvailable {
+                        unavailable_lists: unavailable
+                    }
+                );
+            }
+            answer = selected;
         }
 
         Ok(answer)
     }
 
-    pub fn get_article_range(&self) -> Option<impl Iterator<Item = usize>> {
-        match &self.article_range {
-            Some(range_text) => {
-                // range and multiple lists
-                if self.group_lists.as_ref().is_some_and(|x| x.len() > 1) {
-                    log::warn!(
-                        "article_range used with group_lists with more than one list. This is likely an error"
-                    );
+    /// the groups of `names` selected by group_lists, for groups created after the
+    /// selection was made. Nothing is selected without group_lists
+    pub fn select_new_groups(&self, names: &[String]) -> Result<Vec<GroupConfig>, ConfigError> {
+        if self.group_lists.is_none() {
+            return Ok(vec![]);
+        }
+        Ok(self.select_configured_groups(names)?.0)
+    }
+
+    /// the groups of `list_options` selected by group_lists and not excluded, and the
+    /// names in group_lists missing from `list_options`
+    fn select_configured_groups(
+        &self,
+        list_options: &[String],
+    ) -> Result<(Vec<GroupConfig>, Vec<String>), ConfigError> {
+        let configured = self.configured_groups();
+        for group in configured.iter() {
+            if let Some(range) = &group.range {
+                range_inputs::parse_sequence(range)
+                    .map(drop)
+                    .map_err(|error| ConfigError::InvalidGroupRange {
+                        group: group.name.clone(),
+                        error,
+                    })?;
+            }
+        }
+        let patterns = configured
+            .iter()
+            .map(|group| GroupPattern::parse(&group.name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // groups named in the config keep their own settings, even when a pattern matches them
+        let named: HashSet<&str> = configured
+            .iter()
+            .zip(patterns.iter())
+            .filter(|(_, pattern)| pattern.is_name())
+            .map(|(group, _)| group.name.as_str())
+            .collect();
+        let item_set: HashSet<&str> = list_options.iter().map(String::as_str).collect();
+
+        let mut selected: Vec<GroupConfig> = vec![];
+        let mut unavailable = vec![];
+        for (group, pattern) in configured.iter().zip(patterns.iter()) {
+            if pattern.is_name() {
+                match item_set.contains(group.name.as_str()) {
+                    true => selected.push(group.clone()),
+                    false => unavailable.push(group.name.clone()),
                 }
-                return match range_inputs::parse_sequence(range_text) {
-                    Ok(range) => Some(range),
-                    Err(e) => {
-                        log::error!("Invalid article_range input: {e}");
-                        None
-                    }
-                };
+                continue;
             }
-            None => {
-                return None;
+            // the settings of a pattern apply to the groups it matches
+            let matching: Vec<GroupConfig> = list_options
+                .iter()
+                .filter(|name| pattern.matches(name) && !named.contains(name.as_str()))
+                .filter(|name| !selected.iter().any(|group| &&group.name == name))
+                .map(|name| GroupConfig {
+                    name: name.clone(),
+                    ..group.clone()
+                })
+                .collect();
+            log::info!("{} matches {} lists", group.name, matching.len());
+            log::debug!("Lists selected: {:#?}", matching);
+            selected.extend(matching);
+        }
+
+        let excluded = self.excluded_groups()?;
+        selected.retain(|group| !excluded.iter().any(|pattern| pattern.matches(&group.name)));
+        Ok((selected, unavailable))
+    }
+
+    fn excluded_groups(&self) -> Result<Vec<GroupPattern>, ConfigError> {
+        self.exclude_groups
+            .iter()
+            .flatten()
+            .map(|pattern| GroupPattern::parse(pattern))
+            .collect()
+    }
+
+    /// settings of the entries of group_lists, without checking the server.
+    /// The first entry of a name wins
+    pub fn configured_groups(&self) -> Vec<GroupConfig> {
+        let mut configured: Vec<GroupConfig> = vec![];
+        for entry in self.group_lists.iter().flatten() {
+            if !configured.iter().any(|group| group.name == entry.name()) {
+                configured.push(entry.clone().into_config());
+            }
+        }
+        configured
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_cli_definition() {
+        Opts::command().debug_assert();
+    }
+
+    #[test]
+    fn test_commands() {
+        // no command runs sync
+        let opts = Opts::try_parse_from(["mlh-archiver", "-H", "news.example.org"]).unwrap();
+        assert_eq!(opts.command, None);
+        assert_eq!(
+            opts.sync.app_config.unwrap().hostname.as_deref(),
+            Some("news.example.org")
+        );
+
+        let opts =
+            Opts::try_parse_from(["mlh-archiver", "fetch", "g", "1-3,5", "-p", "563"]).unwrap();
+        let Some(Command::Fetch(args)) = opts.command else {
+            panic!("expected fetch, got {:?}", opts.command);
+        };
+        assert_eq!((args.group.as_str(), args.range.as_str()), ("g", "1-3,5"));
+        assert_eq!(args.config.app_config.unwrap().port, 563);
+
+        assert!(Opts::try_parse_from(["mlh-archiver", "fetch", "g", "3-1"]).is_err());
+        // options go after the command
+        assert!(Opts::try_parse_from(["mlh-archiver", "-H", "x", "status"]).is_err());
+    }
+
+    #[test]
+    fn test_group_lists() {
+        let yaml = "
+group_lists:
+  - test.groups.foo
+  - name: test.groups.bar
+    range: 1-10
+    start_from: 2024-01-31
+    poll_interval: 600
+    priority: 5
+    output_format: maildir
+  - name: test.groups.baz
+    start_from: 1500
+  - ALL
+";
+        #[derive(serde::Deserialize)]
+        struct Lists {
+            group_lists: Vec<GroupEntry>,
+        }
+        let lists: Lists = Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        let mut app_config = AppConfig {
+            group_lists: Some(lists.group_lists),
+            ..Default::default()
+        };
+        let list_options = ["test.groups.bar", "test.groups.baz", "test.groups.qux"]
+            .map(str::to_string)
+            .to_vec();
+
+        let groups = app_config.get_group_lists(list_options.clone()).unwrap();
+        assert_eq!(
+            groups[0],
+            GroupConfig {
+                name: "test.groups.bar".to_string(),
+                range: Some("1-10".to_string()),
+                start_from: Some(StartFrom::Date(
+                    NaiveDate::from_ymd_opt(2024, 1, 31).unwrap()
+                )),
+                poll_interval: Some(600),
+                priority: 5,
+                output_format: Some(OutputFormat::Maildir),
             }
+        );
+        assert_eq!(groups[1].start_from, Some(StartFrom::Article(1500)));
+        assert_eq!(groups[2], GroupConfig::new("test.groups.qux"));
+
+        // without ALL, only the configured groups the server has
+        app_config.group_lists.as_mut().unwrap().pop();
+        let names: Vec<String> = app_config
+            .get_group_lists(list_options.clone())
+            .unwrap()
+            .into_iter()
+            .map(|group| group.name)
+            .collect();
+        assert_eq!(names, vec!["test.groups.bar", "test.groups.baz"]);
+
+        app_config.group_lists = Some(vec![GroupEntry::Settings(GroupConfig {
+            range: Some("10-1".to_string()),
+            ..GroupConfig::new("test.groups.bar")
+        })]);
+        assert!(matches!(
+            app_config.get_group_lists(list_options),
+            Err(ConfigError::InvalidGroupRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_group_patterns() {
+        let list_options = [
+            "linux.kernel.mm",
+            "linux.kernel.fs",
+            "linux.kernel.fs.ext4",
+            "linux.kernel.announce",
+            "org.other",
+        ]
+        .map(str::to_string)
+        .to_vec();
+        let names = |app_config: &mut AppConfig| -> Vec<String> {
+            app_config
+                .get_group_lists(list_options.clone())
+                .unwrap()
+                .into_iter()
+                .map(|group| group.name)
+                .collect()
+        };
+
+        let mut app_config = AppConfig {
+            group_lists: Some(vec![
+                GroupEntry::Settings(GroupConfig {
+                    priority: 5,
+                    ..GroupConfig::new("linux.kernel.*")
+                }),
+                GroupEntry::Name("re:^org\\.".to_string()),
+                GroupEntry::Name("linux.kernel.mm".to_string()),
+            ]),
+            exclude_groups: Some(vec!["*.announce".to_string(), "re:\\.ext4$".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&mut app_config),
+            ["linux.kernel.fs", "org.other", "linux.kernel.mm"]
+        );
+        // the group named on its own keeps its settings
+        let groups = app_config.get_group_lists(list_options.clone()).unwrap();
+        assert_eq!(groups[0].priority, 5);
+        assert_eq!(groups[2].priority, 0);
+
+        app_config.exclude_groups = Some(vec!["ALL".to_string()]);
+        assert!(matches!(
+            app_config.get_group_lists(list_options.clone()),
+            Err(ConfigError::AllListsUnavailable)
+        ));
+
+        app_config.exclude_groups = Some(vec!["re:(".to_string()]);
+        assert!(matches!(
+            app_config.get_group_lists(list_options.clone()),
+            Err(ConfigError::InvalidGroupPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn test_select_new_groups() {
+        let mut app_config = AppConfig {
+            group_lists: Some(vec![
+                GroupEntry::Name("linux.kernel.*".to_string()),
+                GroupEntry::Name("org.named".to_string()),
+            ]),
+            exclude_groups: Some(vec!["*.announce".to_string()]),
+            ..Default::default()
+        };
+        let created = ["linux.kernel.new", "linux.kernel.announce", "org.other"]
+            .map(str::to_string)
+            .to_vec();
+        // no warning nor error when nothing matches
+        assert_eq!(
+            app_config.select_new_groups(&created).unwrap(),
+            vec![GroupConfig::new("linux.kernel.new")]
+        );
+        assert!(app_config.select_new_groups(&[]).unwrap().is_empty());
+
+        app_config.group_lists = None;
+        assert!(app_config.select_new_groups(&created).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_non_interactive() {
+        let mut app_config = AppConfig {
+            non_interactive: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            app_config.get_group_lists(vec!["g".to_string()]),
+            Err(ConfigError::NoListsNonInteractive)
+        ));
+    }
+
+    /// loads the configuration of a command line, with the given environment and files
+    fn load(
+        args: &[&str],
+        env: &[(&str, &str)],
+        xdg_files: Vec<PathBuf>,
+    ) -> (AppConfig, Vec<ConfigSource>) {
+        let matches = Opts::command().try_get_matches_from(args).unwrap();
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let (app_config, _, sources) = load_config(&matches, env, xdg_files).unwrap();
+        (app_config, sources)
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let xdg_file = dir.path().join("config.yaml");
+        fs::write(
+            &xdg_file,
+            "hostname: xdg.example.org\nport: 563\nnthreads: 2\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("nntp_config.yaml"),
+            "hostname: file.example.org\nnthreads: 3\noutput_dir: ./from-file\n",
+        )
+        .unwrap();
+        let config_file = dir.path().join("nntp_config*");
+        let config_file = config_file.to_str().unwrap();
+
+        // defaults only
+        let (app_config, _) = load(&["mlh-archiver", "-c", "/nonexistent"], &[], vec![]);
+        assert_eq!(app_config, AppConfig::defaults());
+        assert_eq!(app_config.port, 119);
+
+        // each layer overrides the previous ones
+        let (app_config, sources) = load(
+            &["mlh-archiver", "status", "-c", config_file, "-n", "5"],
+            &[
+                ("NNTP_NTHREADS", "4"),
+                ("NNTP_OUTPUT_DIR", "./from-env"),
+                ("NNTP_PASSWORD", "secret"),
+            ],
+            vec![xdg_file.clone()],
+        );
+        assert_eq!(app_config.port, 563);
+        assert_eq!(app_config.hostname.as_deref(), Some("file.example.org"));
+        assert_eq!(app_config.output_dir, "./from-env");
+        assert_eq!(app_config.nthreads, 5);
+        // clap defaults of the command line do not override the other layers
+        assert_eq!(app_config.pipeline_depth, 1);
+
+        let layer = |name: &str| {
+            sources
+                .iter()
+                .find(|source| source.name == name)
+                .unwrap()
+                .keys
+                .clone()
+        };
+        assert_eq!(sources.len(), 5);
+        assert_eq!(
+            layer(xdg_file.to_str().unwrap()),
+            ["hostname", "port", "nthreads"]
+        );
+        // unknown variables are not options
+        assert_eq!(layer("environment"), ["output_dir", "nthreads"]);
+        assert_eq!(layer("command line"), ["nthreads"]);
+
+        let printed = format_config(&app_config, &sources).unwrap();
+        assert!(printed.contains("#   command line: nthreads\n"));
+        assert!(printed.contains("\nnthreads: 5\n"));
+        assert!(!printed.contains("secret"));
+    }
+
+    #[test]
+    fn test_group_lists_from_env() {
+        let (app_config, _) = load(
+            &["mlh-archiver", "-c", "/nonexistent"],
+            &[("NNTP_GROUP_LISTS", "test.groups.foo,test.groups.bar")],
+            vec![],
+        );
+        assert_eq!(
+            app_config.group_lists,
+            Some(vec![
+                GroupEntry::Name("test.groups.foo".to_string()),
+                GroupEntry::Name("test.groups.bar".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_xdg_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |dir_name: &str, file_name: &str| {
+            dir.path()
+                .join(dir_name)
+                .join(XDG_CONFIG_FOLDER)
+                .join(file_name)
+        };
+        for file in [
+            path("home", "config.yaml"),
+            path("system1", "config.json"),
+            path("system2", "config.yml"),
+            path(".config", "config.yaml"),
+        ] {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, "").unwrap();
         }
+        let root = dir.path().to_str().unwrap().to_string();
+        let env = |name: &str| match name {
+            "XDG_CONFIG_DIRS" => Some(format!("{root}/system1:{root}/system2")),
+            "XDG_CONFIG_HOME" => Some(format!("{root}/home")),
+            _ => None,
+        };
+        assert_eq!(
+            xdg_config_files(env),
+            vec![
+                path("system2", "config.yml"),
+                path("system1", "config.json"),
+                path("home", "config.yaml"),
+            ]
+        );
+
+        // without XDG variables, ~/.config
+        let env = |name: &str| (name == "HOME").then(|| root.clone());
+        assert!(xdg_config_files(env).ends_with(&[path(".config", "config.yaml")]));
     }
 }
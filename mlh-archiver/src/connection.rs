@@ -0,0 +1,171 @@
+use crate::config::{AppConfig, AuthMechanism, TlsMode};
+use crate::nntp::{self, NNTPStream, NewsGroup};
+use crate::worker;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use std::{env, fs, io, sync::Arc};
+
+const DEFAULT_PASSWORD_ENV: &str = "NNTP_PASSWORD";
+
+/// Everything needed to open (and re-open) a session with the NNTP server
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionConfig {
+    pub hostname: String,
+    pub port: u16,
+    pub tls_mode: TlsMode,
+    pub tls_ca_bundle: Option<String>,
+    pub tls_client_cert: Option<String>,
+    pub tls_client_key: Option<String>,
+    pub username: Option<String>,
+    pub password_file: Option<String>,
+    pub password_env: Option<String>,
+    pub auth_mechanism: AuthMechanism,
+}
+
+impl From<&AppConfig> for ConnectionConfig {
+    fn from(app_config: &AppConfig) -> Self {
+        ConnectionConfig {
+            hostname: app_config.hostname.clone().unwrap_or_default(),
+            port: app_config.port,
+            tls_mode: app_config.tls_mode,
+            tls_ca_bundle: app_config.tls_ca_bundle.clone(),
+            tls_client_cert: app_config.tls_client_cert.clone(),
+            tls_client_key: app_config.tls_client_key.clone(),
+            username: app_config.username.clone(),
+            password_file: app_config.password_file.clone(),
+            password_env: app_config.password_env.clone(),
+            auth_mechanism: app_config.auth_mechanism,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.hostname, self.port)
+    }
+
+    /// reads the password from password_file, or from the password_env variable
+    ///
+    /// It is read on every connection, so rotated secrets are picked up on reconnection.
+    pub fn password(&self) -> io::Result<String> {
+        if let Some(password_file) = &self.password_file {
+            let content = fs::read_to_string(password_file)?;
+            return Ok(content.trim_end_matches(['\r', '\n']).to_string());
+        }
+        let variable = self.password_env.as_deref().unwrap_or(DEFAULT_PASSWORD_ENV);
+        env::var(variable).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("password variable {variable}: {e}"),
+            )
+        })
+    }
+
+    /// builds the rustls client configuration from the CA bundle and client certificate options
+    ///
+    /// Without a CA bundle, the Mozilla root store (webpki-roots) is trusted.
+    pub fn tls_config(&self) -> io::Result<Arc<rustls::ClientConfig>> {
+        let mut root_store = rustls::RootCertStore::empty();
+        match &self.tls_ca_bundle {
+            Some(ca_bundle) => {
+                for cert in CertificateDer::pem_file_iter(ca_bundle).map_err(io::Error::other)? {
+                    root_store
+                        .add(cert.map_err(io::Error::other)?)
+                        .map_err(io::Error::other)?;
+                }
+                log::debug!(
+                    "loaded {} CA certificates from {ca_bundle}",
+                    root_store.len()
+                );
+            }
+            None => root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(root_store);
+
+        let config = match (&self.tls_client_cert, &self.tls_client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let cert_chain = CertificateDer::pem_file_iter(cert_path)
+                    .map_err(io::Error::other)?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(io::Error::other)?;
+                let key = PrivateKeyDer::from_pem_file(key_path).map_err(io::Error::other)?;
+                builder
+                    .with_client_auth_cert(cert_chain, key)
+                    .map_err(io::Error::other)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "tls_client_cert and tls_client_key must be configured together",
+                ));
+            }
+        };
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// The commands a worker sends in a session. Implemented by the NNTP stream, and by
+/// fake servers in tests
+pub trait Session: Send {
+    fn capabilities(&mut self) -> nntp::Result<Vec<String>>;
+    fn group(&mut self, group_name: &str) -> nntp::Result<NewsGroup>;
+    fn over(&mut self, low: isize, high: isize) -> nntp::Result<Vec<String>>;
+    fn listgroup(&mut self, group_name: &str) -> nntp::Result<Vec<isize>>;
+    fn raw_article_by_number(&mut self, article_number: isize) -> nntp::Result<Vec<String>>;
+    /// sends an ARTICLE command without reading the response, see `read_raw_article`
+    fn send_article_by_number(&mut self, article_number: isize) -> nntp::Result<()>;
+    fn read_raw_article(&mut self) -> nntp::Result<Vec<String>>;
+    fn quit(&mut self) -> nntp::Result<()>;
+}
+
+impl Session for NNTPStream {
+    fn capabilities(&mut self) -> nntp::Result<Vec<String>> {
+        NNTPStream::capabilities(self)
+    }
+
+    fn group(&mut self, group_name: &str) -> nntp::Result<NewsGroup> {
+        NNTPStream::group(self, group_name)
+    }
+
+    fn over(&mut self, low: isize, high: isize) -> nntp::Result<Vec<String>> {
+        NNTPStream::over(self, low, high)
+    }
+
+    fn listgroup(&mut self, group_name: &str) -> nntp::Result<Vec<isize>> {
+        NNTPStream::listgroup(self, group_name)
+    }
+
+    fn raw_article_by_number(&mut self, article_number: isize) -> nntp::Result<Vec<String>> {
+        NNTPStream::raw_article_by_number(self, article_number)
+    }
+
+    fn send_article_by_number(&mut self, article_number: isize) -> nntp::Result<()> {
+        NNTPStream::send_article_by_number(self, article_number)
+    }
+
+    fn read_raw_article(&mut self) -> nntp::Result<Vec<String>> {
+        NNTPStream::read_raw_article(self)
+    }
+
+    fn quit(&mut self) -> nntp::Result<()> {
+        NNTPStream::quit(self)
+    }
+}
+
+/// Opens the sessions of a worker: the first one, and a new one after each failure
+pub trait Connector: Send + Sync {
+    fn connect(&self) -> crate::Result<Box<dyn Session>>;
+}
+
+impl Connector for ConnectionConfig {
+    fn connect(&self) -> crate::Result<Box<dyn Session>> {
+        Ok(Box::new(worker::connect_to_nntp(self)?))
+    }
+}
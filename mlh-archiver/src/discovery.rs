@@ -0,0 +1,197 @@
+//! Groups created on the server while the archiver runs.
+//!
+//! The group list is selected once at startup. While syncing, the server is asked
+//! with NEWGROUPS (RFC 3977, section 7.3) for the groups created since the last check,
+//! and those selected by group_lists are added to the groups being read.
+//!
+//! The groups found, and the time of the last check, are kept in the output folder:
+//!
+//! ```text
+//! {output_dir}/__discovered_groups.yml
+//! ```
+
+use crate::config::{AppConfig, GroupConfig};
+use crate::file_utils;
+use crate::nntp::NNTPStream;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const DISCOVERY_FILE_NAME: &str = "__discovered_groups.yml";
+/// each check starts a bit before the previous one, in case the server clock is behind
+const CHECK_OVERLAP: TimeDelta = TimeDelta::minutes(10);
+
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DiscoveryState {
+    pub last_check: Option<DateTime<Utc>>,
+    pub groups: Vec<DiscoveredGroup>,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DiscoveredGroup {
+    pub name: String,
+    pub discovered: DateTime<Utc>,
+}
+
+impl DiscoveryState {
+    /// the saved state, or an empty one if there is none
+    pub fn load(path: &Path) -> io::Result<DiscoveryState> {
+        match path.exists() {
+            true => file_utils::read_yaml(&path.to_string_lossy()),
+            false => Ok(DiscoveryState::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        file_utils::write_yaml(&path.to_string_lossy(), self)
+    }
+}
+
+/// date and time arguments of NEWGROUPS, in GMT: `yyyymmdd`, `hhmmss`
+pub fn newgroups_arguments(since: DateTime<Utc>) -> (String, String) {
+    (
+        since.format("%Y%m%d").to_string(),
+        since.format("%H%M%S").to_string(),
+    )
+}
+
+/// Periodic NEWGROUPS checks, for the scheduler
+pub struct GroupDiscovery {
+    app_config: AppConfig,
+    interval: Duration,
+    state_path: PathBuf,
+    state: DiscoveryState,
+}
+
+impl GroupDiscovery {
+    /// None when the checks are disabled, or no group_lists selects the groups
+    pub fn new(app_config: &AppConfig) -> io::Result<Option<GroupDiscovery>> {
+        if app_config.discover_groups_interval == 0 || app_config.group_lists.is_none() {
+            return Ok(None);
+        }
+        let state_path = Path::new(&app_config.output_dir).join(DISCOVERY_FILE_NAME);
+        let mut state = DiscoveryState::load(&state_path)?;
+        if state.last_check.is_none() {
+            // the groups existing now were just listed
+            state.last_check = Some(Utc::now());
+        }
+
+        Ok(Some(GroupDiscovery {
+            app_config: app_config.clone(),
+            interval: Duration::from_secs(app_config.discover_groups_interval),
+            state_path,
+            state,
+        }))
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// the groups found by earlier runs that group_lists still selects
+    pub fn saved_groups(&self) -> crate::Result<Vec<GroupConfig>> {
+        let names: Vec<String> = self
+            .state
+            .groups
+            .iter()
+            .map(|group| group.name.clone())
+            .collect();
+        Ok(self.app_config.select_new_groups(&names)?)
+    }
+
+    /// asks the server for the groups created since the last check, and returns
+    /// the ones selected by group_lists that are not in `known`
+    pub fn discover(
+        &mut self,
+        nntp_stream: &mut NNTPStream,
+        known: &HashSet<String>,
+    ) -> crate::Result<Vec<GroupConfig>> {
+        let now = Utc::now();
+        let since = self.state.last_check.unwrap_or(now) - CHECK_OVERLAP;
+        let (date, time) = newgroups_arguments(since);
+        let created: Vec<String> = nntp_stream
+            .newgroups(&date, &time, true)?
+            .into_iter()
+            .map(|group| group.name)
+            .filter(|name| !known.contains(name))
+            .collect();
+        log::debug!("{} groups created since {since}", created.len());
+
+        let groups = self.app_config.select_new_groups(&created)?;
+        for group in groups.iter() {
+            self.state.groups.push(DiscoveredGroup {
+                name: group.name.clone(),
+                discovered: now,
+            });
+        }
+        self.state.last_check = Some(now);
+        self.state.save(&self.state_path)?;
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GroupEntry;
+
+    #[test]
+    fn test_newgroups_arguments() {
+        let since = "2024-01-31T09:05:00Z".parse().unwrap();
+        assert_eq!(
+            newgroups_arguments(since),
+            ("20240131".to_string(), "090500".to_string())
+        );
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DISCOVERY_FILE_NAME);
+        assert_eq!(
+            DiscoveryState::load(&path).unwrap(),
+            DiscoveryState::default()
+        );
+
+        let state = DiscoveryState {
+            last_check: "2024-01-31T09:05:00Z".parse().ok(),
+            groups: vec![DiscoveredGroup {
+                name: "test.groups.new".to_string(),
+                discovered: "2024-01-31T09:05:00Z".parse().unwrap(),
+            }],
+        };
+        state.save(&path).unwrap();
+        assert_eq!(DiscoveryState::load(&path).unwrap(), state);
+    }
+
+    #[test]
+    fn test_saved_state_is_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_config = AppConfig {
+            output_dir: dir.path().to_str().unwrap().to_string(),
+            discover_groups_interval: 60,
+            group_lists: Some(vec![GroupEntry::Name("test.*".to_string())]),
+            ..Default::default()
+        };
+        let discovered = |name: &str| DiscoveredGroup {
+            name: name.to_string(),
+            discovered: "2024-01-31T09:05:00Z".parse().unwrap(),
+        };
+        let state = DiscoveryState {
+            last_check: "2024-01-31T09:05:00Z".parse().ok(),
+            groups: vec![discovered("test.groups.new"), discovered("other.group")],
+        };
+        state.save(&dir.path().join(DISCOVERY_FILE_NAME)).unwrap();
+
+        let discovery = GroupDiscovery::new(&app_config).unwrap().unwrap();
+        assert_eq!(discovery.state.last_check, state.last_check);
+        assert_eq!(
+            discovery.saved_groups().unwrap(),
+            vec![GroupConfig::new("test.groups.new")]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::nntp;
+use crate::range_inputs;
 use std::io::{self};
 use std::result;
 use thiserror::Error;
@@ -10,10 +12,15 @@ pub enum Error {
     Unknown,
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
 
     #[allow(clippy::upper_case_acronyms)]
     #[error(transparent)]
     NNTP(#[from] nntp::NNTPError),
+    /// credentials missing or refused by the server. Retrying will not help
+    #[error("authentication failed: {0}")]
+    Authentication(String),
 }
 
 #[derive(Error, Debug)]
@@ -25,7 +32,33 @@ pub enum ConfigError {
     ConfiguredListsNotAvailable { unavailable_lists: Vec<String> },
     #[error("none of the configured lists are available in server")]
     AllListsUnavailable,
+    #[error(
+        "no group_lists configured, and groups cannot be selected without a terminal (non-interactive mode)"
+    )]
+    NoListsNonInteractive,
+    #[error("group selection failed: {0}")]
+    Prompt(String),
+    #[error("invalid group pattern {pattern}: {error}")]
+    InvalidGroupPattern { pattern: String, error: String },
+    #[error("invalid range for group {group}: {error}")]
+    InvalidGroupRange {
+        group: String,
+        error: range_inputs::SequenceParseError,
+    },
 
     #[error(transparent)]
     Io(#[from] io::Error),
 }
+
+/// status code of the server response behind an NNTP error, if the server answered
+/// with one the client did not expect
+pub fn response_code(error: &nntp::NNTPError) -> Option<u16> {
+    let nntp::NNTPError::UnexpectedResponse(response) = error else {
+        return None;
+    };
+    let code = response.trim_start().get(..3)?;
+    match code.bytes().all(|c| c.is_ascii_digit()) {
+        true => code.parse().ok(),
+        false => None,
+    }
+}
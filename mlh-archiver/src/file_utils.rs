@@ -1,28 +1,67 @@
+use crate::compression;
+use crate::config::Compression;
 use serde::de::DeserializeOwned;
 use serde::ser::{self};
 use std::{
     fs::{self, File, OpenOptions},
-    io::{self, LineWriter, Write},
-    path::Path,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
 };
 
+/// writes the lines to a file, compressed if its name ends with `.zst` or `.gz`
+///
+/// The file is replaced atomically (see `write_atomic`).
 pub fn write_lines_file(file_path: &Path, lines: Vec<String>) -> io::Result<()> {
-    // Create or open (truncate) a file for writing
+    let compression = Compression::of_path(file_path);
+    let content = compression.compress(lines.concat().as_bytes())?;
+    write_atomic(file_path, &content)?;
+
+    log::debug!("file written {}", file_path.to_str().unwrap());
+
+    Ok(())
+}
+
+/// reads a file written by `write_lines_file`, keeping the line endings
+pub fn read_lines_file(file_path: &Path) -> io::Result<Vec<String>> {
+    let mut content = String::new();
+    compression::open_reader(file_path)?.read_to_string(&mut content)?;
+    Ok(content.split_inclusive('\n').map(str::to_string).collect())
+}
+
+/// writes the content, and waits for it to reach the disk
+pub fn write_bytes_synced(file_path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(file_path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+/// temporary file used while writing `file_path`: `{file name}.tmp`, in the same folder
+pub fn tmp_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    file_path.with_file_name(name)
+}
+
+/// replaces a file without ever leaving it partially written
+///
+/// The content is written to a temporary file in the same folder and synced, then
+/// renamed over the destination. After a crash, the destination has either the old
+/// or the new content, and at worst a `.tmp` file is left behind.
+pub fn write_atomic(file_path: &Path, content: &[u8]) -> io::Result<()> {
     // check if parent folder need to be created first
     if let Some(parent) = file_path.parent() {
         fs::create_dir_all(parent)?;
     }
-    let file = File::create(file_path)?;
-    let mut file = LineWriter::new(file);
 
-    lines
-        .iter()
-        .for_each(|line| write!(file, "{}", line.as_str()).expect("Cannot write to file"));
-
-    file.flush()?;
-
-    log::debug!("file written {}", file_path.to_str().unwrap());
+    let tmp_path = tmp_path(file_path);
+    write_bytes_synced(&tmp_path, content)?;
+    fs::rename(&tmp_path, file_path)?;
 
+    // make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = file_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
     Ok(())
 }
 
@@ -78,20 +117,9 @@ pub fn write_yaml<T>(file_name: &str, value: &T) -> io::Result<()>
 where
     T: ?Sized + ser::Serialize,
 {
-    // check if parent folder need to be created first
-    let file_path = Path::new(file_name);
-    if let Some(parent) = file_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
-    let f = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(false)
-        .open(file_path)?;
-
-    serde_yaml::to_writer(f, value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    Ok(())
+    let content =
+        serde_yaml::to_string(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    write_atomic(Path::new(file_name), content.as_bytes())
 }
 
 pub fn read_yaml<T>(file_name: &str) -> io::Result<T>
@@ -104,6 +132,17 @@ where
     return Ok(res);
 }
 
+/// names of the folders in a directory, sorted
+pub fn list_folders(path: &str) -> io::Result<Vec<String>> {
+    let mut folders: Vec<String> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    folders.sort();
+    Ok(folders)
+}
+
 pub fn check_or_create_folder(folder_path: String) -> io::Result<()> {
     let p = Path::new(&folder_path);
     if p.exists() {
@@ -117,3 +156,33 @@ pub fn check_or_create_folder(folder_path: String) -> io::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_write_yaml_replaces_longer_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status");
+        let path = path.to_str().unwrap();
+
+        write_yaml(path, &HashMap::from([("last_email", 123456789)])).unwrap();
+        write_yaml(path, &HashMap::from([("last_email", 1)])).unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), "last_email: 1\n");
+        assert!(!tmp_path(Path::new(path)).exists());
+    }
+
+    #[test]
+    fn test_write_lines_file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = vec!["Subject: x\r\n".to_string(), "\r\n".to_string()];
+        for name in ["1.eml", "1.eml.zst", "1.eml.gz"] {
+            let path = dir.path().join("g").join(name);
+            write_lines_file(&path, lines.clone()).unwrap();
+            assert_eq!(read_lines_file(&path).unwrap(), lines);
+        }
+    }
+}
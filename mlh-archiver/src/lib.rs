@@ -1,44 +1,418 @@
 #![allow(clippy::needless_return)]
 
+pub mod compression;
 pub mod config;
+pub mod connection;
+pub mod discovery;
 pub mod errors;
 pub mod file_utils;
+pub mod mbox;
+pub mod message_index;
+pub mod nntp;
+pub mod overview;
 pub mod range_inputs;
+pub mod rate_limit;
+pub mod schedule;
 pub mod scheduler;
+pub mod signals;
+pub mod storage;
+pub mod verify;
 pub mod worker;
 
 pub use errors::Result;
 
-pub fn start(app_config: &mut config::AppConfig) -> crate::errors::Result<()> {
-    let mut nntp_stream = worker::connect_to_nntp(format!(
-        "{}:{}",
-        app_config.hostname.clone().unwrap(),
-        app_config.port
-    ))?;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
-    let list_options = nntp_stream.list().unwrap();
-    let groups = app_config
-        .get_group_lists(list_options.iter().map(move |an| an.clone().name).collect())
-        .unwrap();
+/// keeps the selected groups up-to-date (the `sync` command).
+///
+/// Without loop_groups, checks every group once and prints a summary. Returns false
+/// if a group failed, or if the run was stopped before the end
+pub fn start(app_config: &mut config::AppConfig) -> crate::errors::Result<bool> {
+    let signals = signals::listen()?;
+    loop {
+        let mut groups = select_groups(app_config)?;
+        let discovery = discovery::GroupDiscovery::new(app_config)?;
+        if let Some(discovery) = &discovery {
+            for group in discovery.saved_groups()? {
+                if !groups.iter().any(|selected| selected.name == group.name) {
+                    groups.push(group);
+                }
+            }
+        }
+        let mut scheduler = scheduler(app_config, groups)?
+            .with_schedule(schedule::Schedule::load(app_config)?)
+            .with_signals(
+                signals.clone(),
+                Duration::from_secs(app_config.shutdown_timeout),
+            );
+        if let Some(discovery) = discovery {
+            scheduler = scheduler.with_discovery(discovery);
+        }
+
+        let signal = scheduler.run()?;
+        match signal {
+            Some(signals::Signal::Reload) => {
+                log::info!("Reloading the configuration");
+                match config::read_config() {
+                    Ok((mut new_config, _)) => {
+                        // keeps the groups selected at the prompt, instead of prompting again
+                        if new_config.group_lists.is_none() {
+                            new_config.group_lists = app_config.group_lists.take();
+                        }
+                        *app_config = new_config;
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "Failed reloading the configuration, keeping the current one: {e}"
+                        )
+                    }
+                }
+            }
+            _ if app_config.loop_groups => return Ok(true),
+            _ => {
+                print_summary(scheduler.summary());
+                let failed = scheduler
+                    .summary()
+                    .values()
+                    .any(|summary| summary.failures > 0);
+                return Ok(signal.is_none() && !failed);
+            }
+        }
+    }
+}
+
+/// prints what each group got in a run, and the errors of the failed ones
+fn print_summary(summary: &BTreeMap<String, scheduler::GroupSummary>) {
+    println!(
+        "{:<50} {:>10} {:>8} {:>10}",
+        "group", "articles", "errors", "seconds"
+    );
+    for (group, summary) in summary.iter() {
+        println!(
+            "{:<50} {:>10} {:>8} {:>10.1}",
+            group,
+            summary.new_articles,
+            summary.failures,
+            summary.duration.as_secs_f64()
+        );
+    }
+    for (group, summary) in summary.iter() {
+        if let Some(error) = &summary.last_error {
+            log::error!("{group} failed: {error}");
+        }
+    }
+}
+
+/// fetches a range of articles of one group
+pub fn fetch(
+    app_config: &config::AppConfig,
+    args: &config::FetchArgs,
+) -> crate::errors::Result<()> {
+    let range = range_inputs::parse_sequence(&args.range)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    scheduler(app_config, vec![config::GroupConfig::new(&args.group)])?
+        .run_range(&args.group, range)
+}
+
+/// fetches the articles missing in the selected groups, up to their read status
+pub fn backfill(app_config: &mut config::AppConfig) -> crate::errors::Result<()> {
+    let groups = select_groups(app_config)?;
+    scheduler(app_config, groups)?.run_backfill()
+}
+
+/// prints the groups available in the server
+pub fn list_groups(app_config: &config::AppConfig) -> crate::errors::Result<()> {
+    let connection = connection::ConnectionConfig::from(app_config);
+    let mut nntp_stream = worker::connect_to_nntp(&connection)?;
+    let mut groups = nntp_stream.list()?;
+    let _ = nntp_stream.quit();
+
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    println!(
+        "{:<50} {:>10} {:>10} {:>10}",
+        "group", "articles", "low", "high"
+    );
+    for group in groups {
+        // the server estimate, some numbers in the range may not exist anymore
+        let count = match group.high >= group.low {
+            true => group.high - group.low + 1,
+            false => 0,
+        };
+        println!(
+            "{:<50} {:>10} {:>10} {:>10}",
+            group.name, count, group.low, group.high
+        );
+    }
+    Ok(())
+}
+
+/// prints a summary of the archived groups
+pub fn status(
+    app_config: &config::AppConfig,
+    args: &config::StatusArgs,
+) -> crate::errors::Result<()> {
+    let storage = open_storage(app_config)?;
+    let groups = match args.groups.is_empty() {
+        true => storage.list_groups()?,
+        false => args.groups.clone(),
+    };
+
+    println!(
+        "{:<50} {:>10} {:>12} {:>8}",
+        "group", "articles", "last read", "errors"
+    );
+    for group in groups {
+        println!(
+            "{:<50} {:>10} {:>12} {:>8}",
+            group,
+            storage.list_articles(&group)?.len(),
+            storage.get_read_status(&group)?.last_email,
+            storage.list_errors(&group)?.len()
+        );
+    }
+    Ok(())
+}
+
+/// asks the server for its groups, and picks the configured ones
+fn select_groups(
+    app_config: &mut config::AppConfig,
+) -> crate::errors::Result<Vec<config::GroupConfig>> {
+    let connection = connection::ConnectionConfig::from(&*app_config);
+    let mut nntp_stream = worker::connect_to_nntp(&connection)?;
+
+    let list_options = nntp_stream.list()?;
+    let groups =
+        app_config.get_group_lists(list_options.iter().map(move |an| an.clone().name).collect())?;
 
     // close initial connection to nntp server
     let _ = nntp_stream.quit();
 
-    log::info!("made a selection of {} {:#?}", groups.len(), groups);
-    file_utils::check_or_create_folder(app_config.output_dir.clone())?;
+    log::info!(
+        "made a selection of {} {:#?}",
+        groups.len(),
+        groups.iter().map(|group| &group.name).collect::<Vec<_>>()
+    );
+    Ok(groups)
+}
+
+fn scheduler(
+    app_config: &config::AppConfig,
+    groups: Vec<config::GroupConfig>,
+) -> crate::errors::Result<scheduler::Scheduler> {
+    let inner = open_storage(app_config)?;
+    let storage: Arc<dyn storage::Storage> = match &app_config.mbox_live_dir {
+        Some(mbox_live_dir) => Arc::new(storage::MboxMirror::new(inner, mbox_live_dir)),
+        None => Arc::from(inner),
+    };
 
-    let mut w = scheduler::Scheduler::new(
-        app_config.hostname.clone().unwrap(),
-        app_config.port,
-        app_config.output_dir.clone(),
+    Ok(scheduler::Scheduler::new(
+        connection::ConnectionConfig::from(app_config),
+        storage,
+        app_config.pipeline_depth,
+        app_config.error_retry_max_age_days,
         app_config.nthreads,
         app_config.loop_groups,
         groups,
-    );
-    match app_config.get_article_range() {
-        Some(range) => w.run_range(range),
-        None => w.run(),
-    }?;
+    )
+    .with_rate_limits(
+        rate_limit::Limits {
+            articles_per_second: app_config.max_articles_per_second,
+            bytes_per_second: app_config.max_bytes_per_second,
+        },
+        rate_limit::Limits {
+            articles_per_second: app_config.connection_max_articles_per_second,
+            bytes_per_second: app_config.connection_max_bytes_per_second,
+        },
+        Duration::from_secs(app_config.server_busy_backoff),
+    ))
+}
+
+/// writes the archived groups as mboxrd files
+pub fn export_mbox(
+    app_config: &config::AppConfig,
+    args: &config::ExportMboxArgs,
+) -> crate::errors::Result<()> {
+    let storage = open_storage(app_config)?;
+    let groups = match args.groups.is_empty() {
+        true => storage.list_groups()?,
+        false => args.groups.clone(),
+    };
 
+    for group in groups {
+        mbox::export_group(
+            storage.as_ref(),
+            &group,
+            Path::new(&args.destination),
+            args.split_by_month,
+        )?;
+    }
     Ok(())
 }
+
+/// rewrites the archived .eml files with the configured compression
+pub fn recompress(
+    app_config: &config::AppConfig,
+    args: &config::RecompressArgs,
+) -> crate::errors::Result<()> {
+    let groups = match args.groups.is_empty() {
+        true => file_utils::list_folders(&app_config.output_dir)?,
+        false => args.groups.clone(),
+    };
+
+    // shared by the groups, as duplicates are hard linked across them
+    let mut rewritten = compression::Rewritten::new();
+    for group in groups {
+        let stats = compression::recompress_group(
+            &Path::new(&app_config.output_dir).join(&group),
+            app_config.compression,
+            &mut rewritten,
+        )?;
+        log::info!(
+            "{group}: {} recompressed, {} unchanged, {} failed",
+            stats.recompressed,
+            stats.unchanged,
+            stats.failed
+        );
+    }
+    Ok(())
+}
+
+/// checks the archived groups, printing a report. Returns false if a problem was found
+pub fn verify(
+    app_config: &config::AppConfig,
+    args: &config::VerifyArgs,
+) -> crate::errors::Result<bool> {
+    let storage = open_storage(app_config)?;
+    let groups = match args.groups.is_empty() {
+        true => storage.list_groups()?,
+        false => args.groups.clone(),
+    };
+
+    let mut nntp_stream = match args.server {
+        true => Some(worker::connect_to_nntp(
+            &connection::ConnectionConfig::from(app_config),
+        )?),
+        false => None,
+    };
+
+    let mut reports = vec![];
+    for group in groups {
+        let server_articles = match nntp_stream.as_mut() {
+            Some(nntp_stream) => Some(
+                nntp_stream
+                    .listgroup(&group)?
+                    .into_iter()
+                    .map(|n| n as usize)
+                    .collect::<Vec<_>>(),
+            ),
+            None => None,
+        };
+        reports.push(verify::verify_group(
+            storage.as_ref(),
+            &group,
+            server_articles.as_deref(),
+        )?);
+    }
+    if let Some(mut nntp_stream) = nntp_stream {
+        let _ = nntp_stream.quit();
+    }
+
+    let problems = reports.iter().filter(|r| r.has_problems()).count();
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&reports).map_err(std::io::Error::other)?
+        );
+    } else {
+        for report in &reports {
+            println!("{report}");
+        }
+        println!("{} groups checked, {problems} with problems", reports.len());
+    }
+    Ok(problems == 0)
+}
+
+/// opens the archive in output_dir, in the configured output format
+///
+/// Groups configured with their own output format are kept in a subfolder named
+/// after the format.
+fn open_storage(app_config: &config::AppConfig) -> std::io::Result<Box<dyn storage::Storage>> {
+    let default =
+        open_format_storage(app_config, app_config.output_format, &app_config.output_dir)?;
+
+    let mut formats: HashMap<config::OutputFormat, Arc<dyn storage::Storage>> = HashMap::new();
+    let mut routes = vec![];
+    for group in app_config.configured_groups() {
+        let pattern = config::GroupPattern::parse(&group.name).map_err(std::io::Error::other)?;
+        let storage = match group.output_format {
+            Some(output_format) if output_format != app_config.output_format => {
+                match formats.get(&output_format) {
+                    Some(storage) => Some(storage.clone()),
+                    None => {
+                        let path =
+                            Path::new(&app_config.output_dir).join(output_format.folder_name());
+                        let storage: Arc<dyn storage::Storage> = Arc::from(open_format_storage(
+                            app_config,
+                            output_format,
+                            &path.to_string_lossy(),
+                        )?);
+                        formats.insert(output_format, storage.clone());
+                        Some(storage)
+                    }
+                }
+            }
+            _ => None,
+        };
+        routes.push((pattern, storage));
+    }
+
+    if formats.is_empty() {
+        return Ok(default);
+    }
+    // as when selecting the groups, the named ones win over the patterns
+    routes.sort_by_key(|(pattern, _)| !pattern.is_name());
+    let subfolders = formats
+        .keys()
+        .map(|output_format| output_format.folder_name().to_string())
+        .collect();
+    Ok(Box::new(storage::PerGroupStorage::new(
+        default, routes, subfolders,
+    )))
+}
+
+fn open_format_storage(
+    app_config: &config::AppConfig,
+    output_format: config::OutputFormat,
+    output_dir: &str,
+) -> std::io::Result<Box<dyn storage::Storage>> {
+    return match output_format {
+        config::OutputFormat::Eml => Ok(Box::new(storage::FileStorage::open(
+            output_dir,
+            app_config.duplicates,
+            app_config.compression,
+        )?)),
+        config::OutputFormat::Maildir => {
+            if app_config.duplicates != config::DuplicateMode::Copy {
+                log::warn!("duplicates option is ignored with the maildir output format");
+            }
+            Ok(Box::new(storage::MaildirStorage::open(output_dir)?))
+        }
+        config::OutputFormat::PublicInbox => {
+            if app_config.duplicates != config::DuplicateMode::Copy {
+                log::warn!("duplicates option is ignored with the public-inbox output format");
+            }
+            Ok(Box::new(storage::PublicInboxStorage::open(output_dir)?))
+        }
+        config::OutputFormat::Sqlite => {
+            if app_config.duplicates != config::DuplicateMode::Copy {
+                log::warn!("duplicates option is ignored with the sqlite output format");
+            }
+            Ok(Box::new(storage::SqliteStorage::open(output_dir)?))
+        }
+    };
+}
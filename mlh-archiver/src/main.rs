@@ -3,8 +3,10 @@
 use env_logger::Env;
 
 use mlh_archiver::Result;
-use mlh_archiver::config;
-use mlh_archiver::start;
+use mlh_archiver::config::{self, Command};
+use mlh_archiver::{
+    backfill, export_mbox, fetch, list_groups, message_index, recompress, start, status, verify,
+};
 
 fn main() -> Result<()> {
     let env = Env::default()
@@ -13,6 +15,25 @@ fn main() -> Result<()> {
 
     env_logger::init_from_env(env);
 
-    let mut app_config = config::read_config().unwrap();
-    return start(&mut app_config);
+    let (mut app_config, command) = config::read_config().unwrap();
+    return match command {
+        Command::Sync(_) => match start(&mut app_config)? {
+            true => Ok(()),
+            false => std::process::exit(1),
+        },
+        Command::Fetch(args) => fetch(&app_config, &args),
+        Command::Backfill(_) => backfill(&mut app_config),
+        Command::Groups(_) => list_groups(&app_config),
+        Command::Status(args) => status(&app_config, &args),
+        Command::Verify(args) => match verify(&app_config, &args)? {
+            true => Ok(()),
+            false => std::process::exit(1),
+        },
+        Command::ExportMbox(args) => export_mbox(&app_config, &args),
+        Command::Recompress(args) => recompress(&app_config, &args),
+        Command::RebuildMessageIndex(_) => {
+            message_index::rebuild(&app_config.output_dir)?;
+            Ok(())
+        }
+    };
 }
@@ -0,0 +1,239 @@
+//! mboxrd output, for tools (and `git am` workflows) that want one file per list.
+//!
+//! Messages are separated by a `From <sender> <asctime date>` line, and any body line
+//! starting with `From ` (after any number of `>`) gets one more `>`, so the
+//! quoting can always be reversed.
+
+use crate::message_index::header_from_lines;
+use crate::storage::Storage;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// mbox of the articles without a (valid) Date header, when splitting by month
+const UNDATED_MBOX_NAME: &str = "undated";
+
+/// writes one article in mboxrd format, with LF line endings
+pub fn write_message<W: Write>(writer: &mut W, raw_article: &[String]) -> io::Result<()> {
+    let date = article_date(raw_article)
+        .map(|date| date.with_timezone(&Utc))
+        .unwrap_or_default();
+    writeln!(
+        writer,
+        "From {} {}",
+        envelope_sender(raw_article),
+        date.format("%a %b %e %H:%M:%S %Y")
+    )?;
+
+    for line in raw_article {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim_start_matches('>').starts_with("From ") {
+            writer.write_all(b">")?;
+        }
+        writeln!(writer, "{line}")?;
+    }
+    // empty line between messages
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// `YYYY-MM` of the article's Date header (in UTC)
+pub fn message_month(raw_article: &[String]) -> Option<String> {
+    article_date(raw_article).map(|date| date.with_timezone(&Utc).format("%Y-%m").to_string())
+}
+
+/// `{destination}/{group}/{YYYY-MM}.mbox`, or `{destination}/{group}/undated.mbox`
+pub fn month_mbox_path(destination: &Path, group: &str, raw_article: &[String]) -> PathBuf {
+    let month = message_month(raw_article).unwrap_or_else(|| UNDATED_MBOX_NAME.to_string());
+    destination.join(group).join(format!("{month}.mbox"))
+}
+
+/// appends one article to the mbox of its month. Used to keep mbox files updated while fetching
+pub fn append_to_month_mbox(
+    destination: &Path,
+    group: &str,
+    raw_article: &[String],
+) -> io::Result<()> {
+    let path = month_mbox_path(destination, group, raw_article);
+    let mut writer = open_mbox(&path, true)?;
+    write_message(&mut writer, raw_article)?;
+    writer.flush()
+}
+
+/// writes all stored articles of a group, in article number order, to
+/// `{destination}/{group}.mbox`, or to one mbox per month if `split_by_month` is set
+///
+/// Existing mbox files are replaced. Returns the number of articles exported.
+pub fn export_group(
+    storage: &dyn Storage,
+    group: &str,
+    destination: &Path,
+    split_by_month: bool,
+) -> io::Result<usize> {
+    let mut exported = 0;
+    // mbox files already truncated by this export, later writes append to them
+    let mut created: HashSet<PathBuf> = HashSet::new();
+    let mut current: Option<(PathBuf, BufWriter<File>)> = None;
+
+    for article_number in storage.list_articles(group)? {
+        let Some(raw_article) = storage.get_article(group, article_number)? else {
+            log::warn!("{group}/{article_number} is listed, but could not be read");
+            continue;
+        };
+        let path = match split_by_month {
+            true => month_mbox_path(destination, group, &raw_article),
+            false => destination.join(format!("{group}.mbox")),
+        };
+
+        // articles are mostly in date order, so the same file is usually kept open
+        if current
+            .as_ref()
+            .is_none_or(|(open_path, _)| *open_path != path)
+        {
+            if let Some((_, mut writer)) = current.take() {
+                writer.flush()?;
+            }
+            let append = !created.insert(path.clone());
+            current = Some((path.clone(), open_mbox(&path, append)?));
+        }
+        let (_, writer) = current.as_mut().unwrap();
+        write_message(writer, &raw_article)?;
+        exported += 1;
+    }
+
+    if let Some((_, mut writer)) = current {
+        writer.flush()?;
+    }
+    log::info!("exported {exported} articles of {group}");
+    Ok(exported)
+}
+
+fn open_mbox(path: &Path, append: bool) -> io::Result<BufWriter<File>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+/// parsed Date header of an article
+pub(crate) fn article_date(raw_article: &[String]) -> Option<DateTime<FixedOffset>> {
+    parse_date(&header_from_lines(raw_article, "date")?)
+}
+
+/// parses the value of a Date header (RFC 2822)
+pub(crate) fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|e| {
+            // some clients add a comment the parser does not accept, like "(PST)"
+            match date.rsplit_once('(') {
+                Some((without_comment, _)) => DateTime::parse_from_rfc2822(without_comment.trim()),
+                None => Err(e),
+            }
+        })
+        .ok()
+}
+
+/// address of the Return-Path or From header, or MAILER-DAEMON
+fn envelope_sender(raw_article: &[String]) -> String {
+    ["return-path", "from"]
+        .iter()
+        .filter_map(|name| header_from_lines(raw_article, name))
+        .find_map(|value| {
+            let address = match (value.rfind('<'), value.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &value[start + 1..end],
+                _ => value.split_whitespace().find(|word| word.contains('@'))?,
+            };
+            (!address.is_empty() && !address.contains(char::is_whitespace))
+                .then(|| address.to_string())
+        })
+        .unwrap_or_else(|| "MAILER-DAEMON".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn article(date: &str, body: &[&str]) -> Vec<String> {
+        let mut lines = vec![
+            "From: \"Demo User\" <nobody@example.com>\r\n".to_string(),
+            format!("Date: {date}\r\n"),
+            "Message-ID: <1@example.com>\r\n".to_string(),
+            "\r\n".to_string(),
+        ];
+        lines.extend(body.iter().map(|line| format!("{line}\r\n")));
+        lines
+    }
+
+    #[test]
+    fn test_write_message_quotes_from_lines() {
+        let raw = article(
+            "Tue, 6 Oct 1998 04:38:40 -0500",
+            &["From here", ">From there", "Fromage", " From"],
+        );
+        let mut out = vec![];
+        write_message(&mut out, &raw).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "From nobody@example.com Tue Oct  6 09:38:40 1998\n\
+             From: \"Demo User\" <nobody@example.com>\n\
+             Date: Tue, 6 Oct 1998 04:38:40 -0500\n\
+             Message-ID: <1@example.com>\n\
+             \n\
+             >From here\n\
+             >>From there\n\
+             Fromage\n \
+             From\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn test_message_month() {
+        let raw = article("Wed, 31 Dec 2025 23:30:00 -0300 (BRT)", &[]);
+        assert_eq!(message_month(&raw), Some("2026-01".to_string()));
+        assert_eq!(message_month(&article("yesterday", &[])), None);
+    }
+
+    #[test]
+    fn test_envelope_sender() {
+        let raw = vec!["From: nobody@example.com (Demo)\n".to_string()];
+        assert_eq!(envelope_sender(&raw), "nobody@example.com");
+        assert_eq!(
+            envelope_sender(&["Subject: x\n".to_string()]),
+            "MAILER-DAEMON"
+        );
+    }
+
+    #[test]
+    fn test_export_group_by_month() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = MemoryStorage::new();
+        let jan = article("Thu, 1 Jan 2026 10:00:00 +0000", &["one"]);
+        let feb = article("Sun, 1 Feb 2026 10:00:00 +0000", &["two"]);
+        storage.put_article("g", 1, jan.clone()).unwrap();
+        storage.put_article("g", 2, feb).unwrap();
+        storage.put_article("g", 3, jan).unwrap();
+
+        assert_eq!(export_group(&storage, "g", dir.path(), true).unwrap(), 3);
+        let january = fs::read_to_string(dir.path().join("g/2026-01.mbox")).unwrap();
+        assert_eq!(january.matches("\nFrom ").count() + 1, 2);
+        assert!(dir.path().join("g/2026-02.mbox").is_file());
+
+        // a new export replaces the files
+        assert_eq!(export_group(&storage, "g", dir.path(), false).unwrap(), 3);
+        assert_eq!(export_group(&storage, "g", dir.path(), false).unwrap(), 3);
+        let all = fs::read_to_string(dir.path().join("g.mbox")).unwrap();
+        assert_eq!(all.matches("\nFrom ").count() + 1, 3);
+    }
+}
@@ -0,0 +1,558 @@
+use crate::compression::{self, article_file_name, find_article_file};
+use crate::config::{Compression, DuplicateMode};
+use crate::file_utils;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const INDEX_FILE_NAME: &str = "__message_index";
+const DUPLICATES_FILE_NAME: &str = "__duplicates";
+
+/// Where an article is stored: `{base_output_path}/{group}/{article_number}.eml[.zst|.gz]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArticleLocation {
+    pub group: String,
+    pub article_number: usize,
+}
+
+/// Index of every (group, article number) location of each Message-ID.
+///
+/// It is kept in memory and persisted in `{base_output_path}/__message_index`,
+/// an append only file with one `message-id<TAB>group<TAB>article number` line per location.
+/// It is shared by all workers.
+pub struct MessageIndex {
+    base_output_path: String,
+    duplicate_mode: DuplicateMode,
+    compression: Compression,
+    locations: Mutex<HashMap<String, Vec<ArticleLocation>>>,
+}
+
+impl MessageIndex {
+    /// loads the index from the output directory. A missing index file is an empty index
+    pub fn open(
+        base_output_path: &str,
+        duplicate_mode: DuplicateMode,
+        compression: Compression,
+    ) -> io::Result<MessageIndex> {
+        let mut locations: HashMap<String, Vec<ArticleLocation>> = HashMap::new();
+        let index_path = Path::new(base_output_path).join(INDEX_FILE_NAME);
+        match File::open(&index_path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    match parse_index_line(&line?) {
+                        Some((message_id, location)) => {
+                            let entry = locations.entry(message_id).or_default();
+                            if !entry.contains(&location) {
+                                entry.push(location);
+                            }
+                        }
+                        None => log::warn!("ignoring malformed line in {}", index_path.display()),
+                    }
+                }
+                log::info!(
+                    "loaded {} Message-IDs from {}",
+                    locations.len(),
+                    index_path.display()
+                );
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(MessageIndex {
+            base_output_path: base_output_path.to_string(),
+            duplicate_mode,
+            compression,
+            locations: Mutex::new(locations),
+        })
+    }
+
+    /// every known location of a Message-ID
+    pub fn locations(&self, message_id: &str) -> Vec<ArticleLocation> {
+        self.locations
+            .lock()
+            .unwrap()
+            .get(message_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// records a location, returning false if it was already known
+    pub fn insert(&self, message_id: &str, location: ArticleLocation) -> io::Result<bool> {
+        self.insert_locked(&mut self.locations.lock().unwrap(), message_id, location)
+    }
+
+    fn insert_locked(
+        &self,
+        locations: &mut HashMap<String, Vec<ArticleLocation>>,
+        message_id: &str,
+        location: ArticleLocation,
+    ) -> io::Result<bool> {
+        let entry = locations.entry(message_id.to_string()).or_default();
+        if entry.contains(&location) {
+            return Ok(false);
+        }
+        file_utils::append_line_to_file(
+            &Path::new(&self.base_output_path).join(INDEX_FILE_NAME),
+            &index_line(message_id, &location),
+        )?;
+        entry.push(location);
+        Ok(true)
+    }
+
+    /// stores an article, and records its Message-ID.
+    ///
+    /// If the same Message-ID is already stored in another group, the duplicate mode
+    /// decides if the article is written again, hardlinked to the stored copy, or only
+    /// listed in the group's `__duplicates` manifest.
+    pub fn write_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        let location = ArticleLocation {
+            group: group.to_string(),
+            article_number,
+        };
+        let group_path = Path::new(&self.base_output_path).join(group);
+        let file_path = group_path.join(article_file_name(article_number, self.compression));
+
+        let Some(message_id) = message_id_from_lines(&raw_article) else {
+            log::warn!("no Message-ID found in {}", file_path.display());
+            file_utils::write_lines_file(&file_path, raw_article)?;
+            return remove_article_files(&group_path, article_number, Some(&file_path));
+        };
+
+        if self.duplicate_mode == DuplicateMode::Copy {
+            file_utils::write_lines_file(&file_path, raw_article)?;
+            remove_article_files(&group_path, article_number, Some(&file_path))?;
+            self.insert(&message_id, location)?;
+            return Ok(());
+        }
+
+        // the lookup, the write and the insert happen under the lock, so two workers
+        // storing the same cross-posted article at once do not both write a full copy
+        let mut locations = self.locations.lock().unwrap();
+        let stored_copy = self.stored_copy(&locations, &message_id, &location);
+
+        // the new file is in place before the old one is removed
+        let written = match (self.duplicate_mode, stored_copy) {
+            (DuplicateMode::Hardlink, Some(stored)) => {
+                fs::create_dir_all(&group_path)?;
+                // the link keeps the compression of the stored copy
+                let link_path = group_path.join(article_file_name(
+                    article_number,
+                    Compression::of_path(&stored),
+                ));
+                match hard_link_atomic(&stored, &link_path) {
+                    Ok(()) => Some(link_path),
+                    Err(e) => {
+                        log::warn!(
+                            "could not hardlink {} to {}, writing a copy: {e}",
+                            file_path.display(),
+                            stored.display()
+                        );
+                        file_utils::write_lines_file(&file_path, raw_article)?;
+                        Some(file_path)
+                    }
+                }
+            }
+            (DuplicateMode::Manifest, Some(stored)) => {
+                file_utils::append_line_to_file(
+                    &Path::new(&self.base_output_path)
+                        .join(group)
+                        .join(DUPLICATES_FILE_NAME),
+                    &format!(
+                        "{article_number}\t{message_id}\t{}",
+                        stored
+                            .strip_prefix(&self.base_output_path)
+                            .unwrap_or(&stored)
+                            .display()
+                    ),
+                )?;
+                None
+            }
+            _ => {
+                file_utils::write_lines_file(&file_path, raw_article)?;
+                Some(file_path)
+            }
+        };
+        // an article fetched again may have been stored with another compression
+        remove_article_files(&group_path, article_number, written.as_deref())?;
+
+        self.insert_locked(&mut locations, &message_id, location)?;
+        Ok(())
+    }
+
+    /// path of a stored file for the Message-ID in another location, if any
+    fn stored_copy(
+        &self,
+        locations: &HashMap<String, Vec<ArticleLocation>>,
+        message_id: &str,
+        location: &ArticleLocation,
+    ) -> Option<PathBuf> {
+        locations
+            .get(message_id)?
+            .iter()
+            .filter(|known| *known != location)
+            .find_map(|known| {
+                find_article_file(
+                    &Path::new(&self.base_output_path).join(&known.group),
+                    known.article_number,
+                )
+            })
+    }
+}
+
+/// rebuilds the index file from the articles and duplicate manifests in the output directory
+///
+/// Returns the number of locations indexed.
+pub fn rebuild(base_output_path: &str) -> io::Result<usize> {
+    let base = Path::new(base_output_path);
+    let mut lines: Vec<String> = vec![];
+
+    let mut groups: Vec<PathBuf> = fs::read_dir(base)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    groups.sort();
+
+    for group_path in groups {
+        let Some(group) = group_path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        log::info!("indexing {group}");
+
+        let mut articles: Vec<(usize, PathBuf)> = fs::read_dir(&group_path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| {
+                let (number, _) =
+                    compression::parse_article_file_name(path.file_name()?.to_str()?)?;
+                Some((number, path))
+            })
+            .collect();
+        articles.sort();
+        // the same article with two compressions (an interrupted recompress) is one location
+        articles.dedup_by_key(|(number, _)| *number);
+
+        for (article_number, path) in articles {
+            match read_message_id(&path) {
+                Ok(Some(message_id)) => lines.push(index_line(
+                    &message_id,
+                    &ArticleLocation {
+                        group: group.to_string(),
+                        article_number,
+                    },
+                )),
+                Ok(None) => log::warn!("no Message-ID found in {}", path.display()),
+                Err(e) => log::warn!("could not read {}: {e}", path.display()),
+            }
+        }
+
+        // articles only listed in the manifest are locations too
+        if let Ok(manifest) = fs::read_to_string(group_path.join(DUPLICATES_FILE_NAME)) {
+            for line in manifest.lines() {
+                let mut fields = line.split('\t');
+                if let (Some(number), Some(message_id)) = (fields.next(), fields.next())
+                    && let Ok(article_number) = number.parse::<usize>()
+                {
+                    lines.push(index_line(
+                        message_id,
+                        &ArticleLocation {
+                            group: group.to_string(),
+                            article_number,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    let lines: Vec<String> = lines.into_iter().map(|line| line + "\n").collect();
+    let indexed = lines.len();
+    file_utils::write_lines_file(&base.join(INDEX_FILE_NAME), lines)?;
+
+    log::info!("indexed {indexed} articles in {base_output_path}");
+    Ok(indexed)
+}
+
+/// extracts the Message-ID header from the raw lines of an article
+///
+/// Only the header section (up to the first empty line) is searched, and folded
+/// header values are unfolded.
+pub fn message_id_from_lines<S: AsRef<str>>(lines: &[S]) -> Option<String> {
+    header_from_lines(lines, "message-id").map(|id| id.split_whitespace().collect())
+}
+
+/// extracts the first header named `name` (case insensitive) from the raw lines of an article
+pub fn header_from_lines<S: AsRef<str>>(lines: &[S], name: &str) -> Option<String> {
+    let mut found: Option<String> = None;
+    for line in lines {
+        let line = line.as_ref().trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            // end of headers
+            break;
+        }
+        if let Some(value) = found.as_mut() {
+            if line.starts_with([' ', '\t']) {
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+            break;
+        }
+        if let Some((header, value)) = line.split_once(':')
+            && header.trim().eq_ignore_ascii_case(name)
+        {
+            found = Some(value.trim().to_string());
+        }
+    }
+    found
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// removes the files of an article, except `keep`
+fn remove_article_files(
+    group_path: &Path,
+    article_number: usize,
+    keep: Option<&Path>,
+) -> io::Result<()> {
+    for compression in compression::ALL {
+        let path = group_path.join(article_file_name(article_number, compression));
+        if Some(path.as_path()) == keep {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// links `link_path` to `original` through a temporary name, replacing any file there
+fn hard_link_atomic(original: &Path, link_path: &Path) -> io::Result<()> {
+    let tmp_path = file_utils::tmp_path(link_path);
+    let _ = fs::remove_file(&tmp_path);
+    fs::hard_link(original, &tmp_path)?;
+    fs::rename(&tmp_path, link_path)?;
+    // renaming a link over another link to the same file does nothing
+    let _ = fs::remove_file(&tmp_path);
+    Ok(())
+}
+
+fn read_message_id(path: &Path) -> io::Result<Option<String>> {
+    let reader = compression::open_reader(path)?;
+    let mut header_lines = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim_end().is_empty() {
+            break;
+        }
+        header_lines.push(line);
+    }
+    Ok(message_id_from_lines(&header_lines))
+}
+
+fn index_line(message_id: &str, location: &ArticleLocation) -> String {
+    format!(
+        "{message_id}\t{}\t{}",
+        location.group, location.article_number
+    )
+}
+
+fn parse_index_line(line: &str) -> Option<(String, ArticleLocation)> {
+    let mut fields = line.split('\t');
+    let message_id = fields.next()?.to_string();
+    let group = fields.next()?.to_string();
+    let article_number = fields.next()?.trim().parse::<usize>().ok()?;
+    Some((
+        message_id,
+        ArticleLocation {
+            group,
+            article_number,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn article(message_id: &str) -> Vec<String> {
+        vec![
+            "From: John Doe <j.doe@example.org>\r\n".to_string(),
+            format!("Message-ID: {message_id}\r\n"),
+            "\r\n".to_string(),
+            "body\r\n".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_message_id_from_lines() {
+        assert_eq!(
+            message_id_from_lines(&article("<a@example.org>")),
+            Some("<a@example.org>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_message_id_case_and_folding() {
+        let lines = [
+            "Subject: x",
+            "message-id:",
+            " <folded@example.org>",
+            "",
+            "body",
+        ];
+        assert_eq!(
+            message_id_from_lines(&lines),
+            Some("<folded@example.org>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_message_id_only_in_headers() {
+        let lines = ["Subject: x", "", "Message-ID: <in-body@example.org>"];
+        assert_eq!(message_id_from_lines(&lines), None);
+    }
+
+    #[test]
+    fn test_index_line_roundtrip() {
+        let location = ArticleLocation {
+            group: "test.groups.foo".to_string(),
+            article_number: 42,
+        };
+        assert_eq!(
+            parse_index_line(&index_line("<a@b>", &location)),
+            Some(("<a@b>".to_string(), location))
+        );
+        assert_eq!(parse_index_line("<a@b>\tgroup"), None);
+    }
+
+    #[test]
+    fn test_duplicates_and_rebuild() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+
+        let index = MessageIndex::open(base, DuplicateMode::Hardlink, Compression::None).unwrap();
+        index
+            .write_article("test.groups.foo", 1, article("<a@example.org>"))
+            .unwrap();
+        index
+            .write_article("test.groups.bar", 7, article("<a@example.org>"))
+            .unwrap();
+        assert_eq!(index.locations("<a@example.org>").len(), 2);
+        assert!(dir.path().join("test.groups.bar/7.eml").is_file());
+
+        let manifest_index =
+            MessageIndex::open(base, DuplicateMode::Manifest, Compression::Zstd).unwrap();
+        // loaded from disk
+        assert_eq!(manifest_index.locations("<a@example.org>").len(), 2);
+        manifest_index
+            .write_article("test.groups.baz", 3, article("<a@example.org>"))
+            .unwrap();
+        assert!(!dir.path().join("test.groups.baz/3.eml").exists());
+        assert!(dir.path().join("test.groups.baz/__duplicates").is_file());
+
+        fs::remove_file(dir.path().join(INDEX_FILE_NAME)).unwrap();
+        assert_eq!(rebuild(base).unwrap(), 3);
+        let rebuilt = MessageIndex::open(base, DuplicateMode::Copy, Compression::None).unwrap();
+        assert_eq!(rebuilt.locations("<a@example.org>").len(), 3);
+    }
+
+    #[test]
+    fn test_concurrent_duplicates_are_linked() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let index = MessageIndex::open(base, DuplicateMode::Hardlink, Compression::None).unwrap();
+
+        // one cross-posted article stored by a worker per group, at the same time
+        std::thread::scope(|scope| {
+            for group in 0..8 {
+                let index = &index;
+                scope.spawn(move || {
+                    index
+                        .write_article(
+                            &format!("test.groups.{group}"),
+                            1,
+                            article("<a@example.org>"),
+                        )
+                        .unwrap();
+                });
+            }
+        });
+        let inodes: HashSet<u64> = (0..8)
+            .map(|group| {
+                fs::metadata(dir.path().join(format!("test.groups.{group}/1.eml")))
+                    .unwrap()
+                    .ino()
+            })
+            .collect();
+        assert_eq!(inodes.len(), 1);
+        assert_eq!(index.locations("<a@example.org>").len(), 8);
+    }
+
+    #[test]
+    fn test_compressed_articles() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+
+        let index = MessageIndex::open(base, DuplicateMode::Hardlink, Compression::Zstd).unwrap();
+        index
+            .write_article("test.groups.foo", 1, article("<a@example.org>"))
+            .unwrap();
+        assert!(dir.path().join("test.groups.foo/1.eml.zst").is_file());
+
+        // the link keeps the compression of the stored copy
+        let index = MessageIndex::open(base, DuplicateMode::Hardlink, Compression::Gzip).unwrap();
+        index
+            .write_article("test.groups.bar", 2, article("<a@example.org>"))
+            .unwrap();
+        assert!(dir.path().join("test.groups.bar/2.eml.zst").is_file());
+
+        // fetched again with another compression, only the new file is kept
+        let index = MessageIndex::open(base, DuplicateMode::Copy, Compression::Gzip).unwrap();
+        index
+            .write_article("test.groups.foo", 1, article("<a@example.org>"))
+            .unwrap();
+        assert!(dir.path().join("test.groups.foo/1.eml.gz").is_file());
+        assert!(!dir.path().join("test.groups.foo/1.eml.zst").exists());
+
+        fs::remove_file(dir.path().join(INDEX_FILE_NAME)).unwrap();
+        assert_eq!(rebuild(base).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_write_article_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let index = MessageIndex::open(base, DuplicateMode::Hardlink, Compression::None).unwrap();
+        for (group, article_number) in [("test.groups.foo", 1), ("test.groups.bar", 2)] {
+            for _ in 0..2 {
+                index
+                    .write_article(group, article_number, article("<a@example.org>"))
+                    .unwrap();
+            }
+        }
+
+        let bar: Vec<_> = fs::read_dir(dir.path().join("test.groups.bar"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(bar, vec!["2.eml"]);
+        assert_eq!(
+            file_utils::read_lines_file(&dir.path().join("test.groups.bar/2.eml")).unwrap(),
+            article("<a@example.org>")
+        );
+    }
+}
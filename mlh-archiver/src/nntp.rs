@@ -0,0 +1,609 @@
+//! A blocking NNTP client (RFC 3977), with TLS (RFC 4642, RFC 8143) and
+//! authentication (RFC 4643). Only the commands the archiver sends are implemented.
+//!
+//! Multi-line responses are returned as lines with the dot-stuffing removed. Articles
+//! keep the line endings sent by the server, so they are stored as received; the
+//! other listings do not. Lines that are not valid UTF-8 are read as Latin-1, so no
+//! byte of an article is lost.
+
+pub mod errors;
+
+pub use errors::NNTPError;
+
+use rustls::pki_types::ServerName;
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
+
+pub type Result<T> = std::result::Result<T, NNTPError>;
+
+/// how long connecting, or a read or write, may block before the connection is
+/// considered broken
+const IO_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// a group, as listed by LIST ACTIVE, NEWGROUPS or selected by GROUP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewsGroup {
+    pub name: String,
+    pub high: isize,
+    pub low: isize,
+    /// posting status from LIST ACTIVE (`y`, `n`, `m`...), empty after GROUP
+    pub status: String,
+}
+
+impl NewsGroup {
+    /// parses a line of LIST ACTIVE or NEWGROUPS: `name high low status`
+    fn from_active_line(line: &str) -> Option<NewsGroup> {
+        let mut fields = line.split_whitespace();
+        Some(NewsGroup {
+            name: fields.next()?.to_string(),
+            high: fields.next()?.parse().ok()?,
+            low: fields.next()?.parse().ok()?,
+            status: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for NewsGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}-{})", self.name, self.low, self.high)
+    }
+}
+
+enum Transport {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Transport {
+    fn tls(
+        tcp_stream: TcpStream,
+        server_name: &str,
+        config: Arc<rustls::ClientConfig>,
+    ) -> io::Result<Transport> {
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection =
+            rustls::ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+        Ok(Transport::Tls(Box::new(rustls::StreamOwned::new(
+            connection, tcp_stream,
+        ))))
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+fn tcp_connect(address: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, IO_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(IO_TIMEOUT))?;
+                stream.set_write_timeout(Some(IO_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// a session with an NNTP server
+pub struct NNTPStream {
+    stream: BufReader<Transport>,
+}
+
+impl NNTPStream {
+    /// connects in plain text, and reads the greeting of the server
+    pub fn connect(address: impl ToSocketAddrs) -> Result<NNTPStream> {
+        NNTPStream::open(Transport::Plain(tcp_connect(address)?))
+    }
+
+    /// connects with implicit TLS (usually on port 563), and reads the greeting
+    pub fn connect_tls(
+        address: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<NNTPStream> {
+        let tcp_stream = tcp_connect(address)?;
+        NNTPStream::open(Transport::tls(tcp_stream, server_name, config)?)
+    }
+
+    fn open(transport: Transport) -> Result<NNTPStream> {
+        let mut nntp_stream = NNTPStream {
+            stream: BufReader::new(transport),
+        };
+        // 200 posting allowed, 201 posting prohibited. 400 and 502 refuse the client
+        let (code, line) = nntp_stream.read_response()?;
+        match code {
+            200 | 201 => Ok(nntp_stream),
+            _ => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    /// upgrades a plain text session to TLS
+    pub fn starttls(
+        mut self,
+        server_name: &str,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<NNTPStream> {
+        self.command("STARTTLS", 382)?;
+        // anything sent before the handshake is not protected, and must not be read
+        // as if it came through TLS
+        if !self.stream.buffer().is_empty() {
+            return Err(NNTPError::UnexpectedResponse(
+                "data received before the TLS handshake".to_string(),
+            ));
+        }
+        match self.stream.into_inner() {
+            Transport::Plain(tcp_stream) => Ok(NNTPStream {
+                stream: BufReader::new(Transport::tls(tcp_stream, server_name, config)?),
+            }),
+            Transport::Tls(_) => Err(NNTPError::UnexpectedResponse(
+                "STARTTLS on a TLS session".to_string(),
+            )),
+        }
+    }
+
+    /// AUTHINFO USER, followed by AUTHINFO PASS when the server asks for it
+    pub fn authinfo_user_pass(&mut self, username: &str, password: &str) -> Result<()> {
+        self.send(&format!("AUTHINFO USER {username}"))?;
+        match self.read_response()? {
+            (281, _) => Ok(()),
+            (381, _) => {
+                self.send(&format!("AUTHINFO PASS {password}"))?;
+                self.expect(281).map(|_| ())
+            }
+            (_, line) => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    /// AUTHINFO SASL PLAIN, with the initial response
+    pub fn authinfo_sasl_plain(&mut self, username: &str, password: &str) -> Result<()> {
+        let credentials = base64(format!("\0{username}\0{password}").as_bytes());
+        self.send(&format!("AUTHINFO SASL PLAIN {credentials}"))?;
+        self.expect(281).map(|_| ())
+    }
+
+    pub fn capabilities(&mut self) -> Result<Vec<String>> {
+        self.command("CAPABILITIES", 101)?;
+        self.read_lines()
+    }
+
+    /// every group of the server (LIST ACTIVE)
+    pub fn list(&mut self) -> Result<Vec<NewsGroup>> {
+        self.command("LIST", 215)?;
+        Ok(self
+            .read_lines()?
+            .iter()
+            .filter_map(|line| NewsGroup::from_active_line(line))
+            .collect())
+    }
+
+    /// the groups created since `date` and `time` (`yyyymmdd`, `hhmmss`)
+    pub fn newgroups(&mut self, date: &str, time: &str, use_gmt: bool) -> Result<Vec<NewsGroup>> {
+        let gmt = if use_gmt { " GMT" } else { "" };
+        self.command(&format!("NEWGROUPS {date} {time}{gmt}"), 231)?;
+        Ok(self
+            .read_lines()?
+            .iter()
+            .filter_map(|line| NewsGroup::from_active_line(line))
+            .collect())
+    }
+
+    /// selects a group: `211 count low high name`
+    pub fn group(&mut self, group_name: &str) -> Result<NewsGroup> {
+        self.send(&format!("GROUP {group_name}"))?;
+        let line = self.expect_group()?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let number = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(|| NNTPError::UnexpectedResponse(line.clone()))
+        };
+        Ok(NewsGroup {
+            name: group_name.to_string(),
+            low: number(2)?,
+            high: number(3)?,
+            status: String::new(),
+        })
+    }
+
+    /// the article numbers of a group, which is selected
+    pub fn listgroup(&mut self, group_name: &str) -> Result<Vec<isize>> {
+        self.send(&format!("LISTGROUP {group_name}"))?;
+        self.expect_group()?;
+        Ok(self
+            .read_lines()?
+            .iter()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect())
+    }
+
+    /// the overview lines of the articles from `low` to `high`, in the selected group
+    pub fn over(&mut self, low: isize, high: isize) -> Result<Vec<String>> {
+        self.command(&format!("OVER {low}-{high}"), 224)?;
+        self.read_lines()
+    }
+
+    pub fn raw_article_by_number(&mut self, article_number: isize) -> Result<Vec<String>> {
+        self.send_article_by_number(article_number)?;
+        self.read_raw_article()
+    }
+
+    /// sends ARTICLE without reading the response, so several can be in flight. The
+    /// responses are read in order with `read_raw_article`
+    pub fn send_article_by_number(&mut self, article_number: isize) -> Result<()> {
+        self.send(&format!("ARTICLE {article_number}"))
+    }
+
+    /// reads the response to the oldest ARTICLE sent
+    pub fn read_raw_article(&mut self) -> Result<Vec<String>> {
+        match self.read_response()? {
+            (220, _) => self.read_raw_lines(),
+            (423 | 430, _) => Err(NNTPError::ArticleUnavailable),
+            (_, line) => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    pub fn quit(&mut self) -> Result<()> {
+        self.command("QUIT", 205).map(|_| ())
+    }
+
+    fn send(&mut self, command: &str) -> Result<()> {
+        let transport = self.stream.get_mut();
+        transport.write_all(command.as_bytes())?;
+        transport.write_all(b"\r\n")?;
+        transport.flush()?;
+        Ok(())
+    }
+
+    /// sends a command, and reads its status line
+    fn command(&mut self, command: &str, expected: u16) -> Result<String> {
+        self.send(command)?;
+        self.expect(expected)
+    }
+
+    fn expect(&mut self, expected: u16) -> Result<String> {
+        match self.read_response()? {
+            (code, line) if code == expected => Ok(line),
+            (_, line) => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    /// the response to GROUP and LISTGROUP
+    fn expect_group(&mut self) -> Result<String> {
+        match self.read_response()? {
+            (211, line) => Ok(line),
+            (411, _) => Err(NNTPError::NoSuchGroup),
+            (_, line) => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    /// reads a status line, and its code
+    fn read_response(&mut self) -> Result<(u16, String)> {
+        let line = self.read_line()?;
+        match line.get(..3).and_then(|code| code.parse().ok()) {
+            Some(code) => Ok((code, line)),
+            None => Err(NNTPError::UnexpectedResponse(line)),
+        }
+    }
+
+    /// reads the lines of a multi-line response, up to the terminating dot
+    fn read_lines(&mut self) -> Result<Vec<String>> {
+        Ok(self
+            .read_raw_lines()?
+            .into_iter()
+            .map(|line| line.trim_end_matches(['\r', '\n']).to_string())
+            .collect())
+    }
+
+    /// reads the lines of a multi-line response, with their line ending
+    fn read_raw_lines(&mut self) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let line = self.read_raw_line()?;
+            if line.trim_end_matches(['\r', '\n']) == "." {
+                return Ok(lines);
+            }
+            match line.strip_prefix('.') {
+                Some(unstuffed) => lines.push(unstuffed.to_string()),
+                None => lines.push(line),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let line = self.read_raw_line()?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn read_raw_line(&mut self) -> Result<String> {
+        let mut line = vec![];
+        if self.stream.read_until(b'\n', &mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        match String::from_utf8(line) {
+            Ok(line) => Ok(line),
+            Err(e) => Ok(e.into_bytes().into_iter().map(char::from).collect()),
+        }
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => output.push(ALPHABET[(triple >> (18 - 6 * index) & 0x3f) as usize] as char),
+                false => output.push('='),
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{SocketAddr, TcpListener},
+        thread::{self, JoinHandle},
+    };
+
+    /// a server sending `greeting`, then answering each expected command with its
+    /// response, in order
+    fn fake_server(
+        greeting: &'static [u8],
+        script: Vec<(&'static str, &'static [u8])>,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer.write_all(greeting).unwrap();
+            for (command, response) in script {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert_eq!(line, format!("{command}\r\n"));
+                writer.write_all(response).unwrap();
+            }
+        });
+        (address, handle)
+    }
+
+    const READY: &[u8] = b"200 ready\r\n";
+
+    #[test]
+    fn test_group_and_articles() {
+        let (address, server) = fake_server(
+            READY,
+            vec![
+                ("GROUP test.group", b"211 3 1 3 test.group\r\n"),
+                (
+                    "ARTICLE 1",
+                    b"220 1 <1@example.com>\r\nSubject: one\r\n\r\n..dot\r\nbody\r\n.\r\n",
+                ),
+                ("ARTICLE 2", b"423 no such article\r\n"),
+                ("GROUP missing.group", b"411 no such group\r\n"),
+                ("QUIT", b"205 bye\r\n"),
+            ],
+        );
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+
+        let group = nntp_stream.group("test.group").unwrap();
+        assert_eq!((group.low, group.high), (1, 3));
+        assert_eq!(
+            nntp_stream.raw_article_by_number(1).unwrap(),
+            vec!["Subject: one\r\n", "\r\n", ".dot\r\n", "body\r\n"]
+        );
+        assert!(matches!(
+            nntp_stream.raw_article_by_number(2),
+            Err(NNTPError::ArticleUnavailable)
+        ));
+        assert!(matches!(
+            nntp_stream.group("missing.group"),
+            Err(NNTPError::NoSuchGroup)
+        ));
+        nntp_stream.quit().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_pipelined_articles() {
+        let (address, server) = fake_server(
+            READY,
+            vec![
+                ("ARTICLE 1", b"220 1 <1@example.com>\r\none\r\n.\r\n"),
+                ("ARTICLE 2", b"220 2 <2@example.com>\r\ntwo\r\n.\r\n"),
+            ],
+        );
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+
+        nntp_stream.send_article_by_number(1).unwrap();
+        nntp_stream.send_article_by_number(2).unwrap();
+        assert_eq!(nntp_stream.read_raw_article().unwrap(), vec!["one\r\n"]);
+        assert_eq!(nntp_stream.read_raw_article().unwrap(), vec!["two\r\n"]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_listings() {
+        let (address, server) = fake_server(
+            READY,
+            vec![
+                (
+                    "CAPABILITIES",
+                    b"101 capabilities\r\nVERSION 2\r\nREADER\r\nOVER\r\n.\r\n",
+                ),
+                (
+                    "LIST",
+                    b"215 list\r\na.group 10 2 y\r\nb.group 0 1 n\r\n.\r\n",
+                ),
+                (
+                    "NEWGROUPS 20240101 000000 GMT",
+                    b"231 new groups\r\nc.group 5 1 y\r\n.\r\n",
+                ),
+                (
+                    "LISTGROUP a.group",
+                    b"211 2 2 10 a.group\r\n2\r\n10\r\n.\r\n",
+                ),
+                ("OVER 2-10", b"224 overview\r\n2\tsubject\r\n.\r\n"),
+                ("OVER 11-20", b"423 no articles in that range\r\n"),
+            ],
+        );
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+
+        assert_eq!(
+            nntp_stream.capabilities().unwrap(),
+            vec!["VERSION 2", "READER", "OVER"]
+        );
+        let groups = nntp_stream.list().unwrap();
+        assert_eq!(
+            groups[0],
+            NewsGroup {
+                name: "a.group".to_string(),
+                high: 10,
+                low: 2,
+                status: "y".to_string(),
+            }
+        );
+        assert_eq!(groups[1].name, "b.group");
+        assert_eq!(
+            nntp_stream.newgroups("20240101", "000000", true).unwrap()[0].name,
+            "c.group"
+        );
+        assert_eq!(nntp_stream.listgroup("a.group").unwrap(), vec![2, 10]);
+        assert_eq!(nntp_stream.over(2, 10).unwrap(), vec!["2\tsubject"]);
+        match nntp_stream.over(11, 20) {
+            Err(NNTPError::UnexpectedResponse(line)) => assert!(line.starts_with("423")),
+            other => panic!("unexpected {other:?}"),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_authentication() {
+        let (address, server) = fake_server(
+            READY,
+            vec![
+                ("AUTHINFO USER user", b"381 password required\r\n"),
+                ("AUTHINFO PASS pass", b"281 welcome\r\n"),
+                ("AUTHINFO SASL PLAIN AHVzZXIAcGFzcw==", b"281 welcome\r\n"),
+                ("AUTHINFO USER user", b"381 password required\r\n"),
+                ("AUTHINFO PASS wrong", b"481 rejected\r\n"),
+            ],
+        );
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+
+        nntp_stream.authinfo_user_pass("user", "pass").unwrap();
+        nntp_stream.authinfo_sasl_plain("user", "pass").unwrap();
+        assert!(matches!(
+            nntp_stream.authinfo_user_pass("user", "wrong"),
+            Err(NNTPError::UnexpectedResponse(line)) if line.starts_with("481")
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_refused_and_broken_connections() {
+        let (address, server) = fake_server(b"400 too many connections\r\n", vec![]);
+        assert!(matches!(
+            NNTPStream::connect(address),
+            Err(NNTPError::UnexpectedResponse(line)) if line.starts_with("400")
+        ));
+        server.join().unwrap();
+
+        // the server closes the connection without answering
+        let (address, server) = fake_server(READY, vec![]);
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+        server.join().unwrap();
+        let error = nntp_stream.group("test.group").unwrap_err();
+        assert!(errors::check_network_error(&error), "{error}");
+    }
+
+    #[test]
+    fn test_starttls_refuses_injected_data() {
+        let (address, server) = fake_server(
+            READY,
+            vec![("STARTTLS", b"382 continue\r\n200 injected\r\n")],
+        );
+        let nntp_stream = NNTPStream::connect(address).unwrap();
+        let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth();
+
+        assert!(matches!(
+            nntp_stream.starttls("localhost", Arc::new(config)),
+            Err(NNTPError::UnexpectedResponse(_))
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_latin1_lines() {
+        let (address, server) = fake_server(
+            READY,
+            vec![(
+                "ARTICLE 1",
+                b"220 1 <1@example.com>\r\nFrom: Jos\xe9\r\n.\r\n",
+            )],
+        );
+        let mut nntp_stream = NNTPStream::connect(address).unwrap();
+        assert_eq!(
+            nntp_stream.raw_article_by_number(1).unwrap(),
+            vec!["From: Jos\u{e9}\r\n"]
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"\0user\0pass"), "AHVzZXIAcGFzcw==");
+    }
+}
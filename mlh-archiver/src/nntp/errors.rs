@@ -0,0 +1,22 @@
+use std::io;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NNTPError {
+    /// 423 or 430: no article with that number or message-id
+    #[error("article unavailable")]
+    ArticleUnavailable,
+    /// 411: the group does not exist on the server
+    #[error("no such group")]
+    NoSuchGroup,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// the status line of a response the command does not expect
+    #[error("unexpected response: {0}")]
+    UnexpectedResponse(String),
+}
+
+/// whether the connection itself failed, and a new one is needed
+pub fn check_network_error(error: &NNTPError) -> bool {
+    matches!(error, NNTPError::Io(_))
+}
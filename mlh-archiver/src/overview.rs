@@ -0,0 +1,168 @@
+use crate::errors::response_code;
+use crate::nntp;
+use chrono::{DateTime, Utc};
+
+/// One line of an OVER/XOVER response (RFC 3977, section 8.3).
+///
+/// Only the fields the archiver needs are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverviewEntry {
+    pub article_number: usize,
+    pub message_id: String,
+    /// article size in bytes, as reported by the server. 0 when unknown
+    pub bytes: usize,
+    /// the Date header, if it could be parsed
+    pub date: Option<DateTime<Utc>>,
+}
+
+/// Parses one tab separated overview line.
+///
+/// The fields are: number, subject, from, date, message-id, references, bytes, lines,
+/// followed by optional extra headers. Returns `None` if the article number or the
+/// Message-ID are missing, as such entries cannot be used to fetch or index articles.
+pub fn parse_overview_line(line: &str) -> Option<OverviewEntry> {
+    let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+
+    let article_number = fields.first()?.trim().parse::<usize>().ok()?;
+    let message_id = fields.get(4)?.trim();
+    if message_id.is_empty() {
+        return None;
+    }
+    let bytes = fields
+        .get(6)
+        .and_then(|b| b.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let date = fields
+        .get(3)
+        .and_then(|date| crate::mbox::parse_date(date.trim()))
+        .map(|date| date.with_timezone(&Utc));
+
+    Some(OverviewEntry {
+        article_number,
+        message_id: message_id.to_string(),
+        bytes,
+        date,
+    })
+}
+
+/// Parses an OVER/XOVER response, skipping (and logging) malformed lines.
+/// The entries are sorted by article number.
+pub fn parse_overview(lines: &[String]) -> Vec<OverviewEntry> {
+    let mut entries: Vec<OverviewEntry> = lines
+        .iter()
+        .filter_map(|line| {
+            let entry = parse_overview_line(line);
+            if entry.is_none() {
+                log::warn!("ignoring malformed overview line: {line}");
+            }
+            entry
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.article_number);
+    entries
+}
+
+/// Checks if the server advertised the OVER command in its CAPABILITIES response.
+pub fn supports_over(capabilities: &[String]) -> bool {
+    capabilities.iter().any(|line| {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("OVER"))
+    })
+}
+
+/// The server has no article in the range of an OVER command (423): an empty batch
+pub fn no_articles_in_range(error: &nntp::NNTPError) -> bool {
+    matches!(error, nntp::NNTPError::ArticleUnavailable) || response_code(error) == Some(423)
+}
+
+/// The server does not know or does not allow the OVER command (500, 501 or 503)
+pub fn over_unsupported(error: &nntp::NNTPError) -> bool {
+    matches!(response_code(error), Some(500 | 501 | 503))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_line() {
+        let line = "3000234\tI am just a test article\t\"Demo User\" <nobody@example.com>\t6 Oct 1998 04:38:40 -0500\t<45223423@example.com>\t<45454@example.net>\t1234\t17\tXref: news.example.com misc.test:3000363";
+        assert_eq!(
+            parse_overview_line(line),
+            Some(OverviewEntry {
+                article_number: 3000234,
+                message_id: "<45223423@example.com>".to_string(),
+                bytes: 1234,
+                date: "1998-10-06T09:38:40Z".parse().ok(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_line_with_missing_bytes() {
+        let line = "12\tsubject\tfrom\tdate\t<id@example.com>\t\t\t";
+        assert_eq!(parse_overview_line(line).unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_parse_line_with_crlf() {
+        let line = "7\ts\tf\td\t<id@example.com>\t\t10\t1\r\n";
+        assert_eq!(parse_overview_line(line).unwrap().article_number, 7);
+    }
+
+    #[test]
+    fn test_fail_on_invalid_number() {
+        assert_eq!(
+            parse_overview_line("abc\ts\tf\td\t<id@example.com>\t\t10\t1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fail_on_missing_message_id() {
+        assert_eq!(parse_overview_line("1\ts\tf\td\t\t\t10\t1"), None);
+        assert_eq!(parse_overview_line("1\ts\tf"), None);
+    }
+
+    #[test]
+    fn test_parse_overview_sorts_and_skips() {
+        let lines = vec![
+            "5\ts\tf\td\t<5@x>\t\t10\t1".to_string(),
+            "garbage".to_string(),
+            "2\ts\tf\td\t<2@x>\t\t10\t1".to_string(),
+        ];
+        let numbers: Vec<usize> = parse_overview(&lines)
+            .iter()
+            .map(|e| e.article_number)
+            .collect();
+        assert_eq!(numbers, vec![2, 5]);
+    }
+
+    #[test]
+    fn test_supports_over() {
+        let caps = |c: &[&str]| c.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(supports_over(&caps(&["VERSION 2", "READER", "OVER"])));
+        assert!(supports_over(&caps(&["VERSION 2", "OVER MSGID"])));
+        assert!(!supports_over(&caps(&["VERSION 2", "READER", "HDR"])));
+        assert!(!supports_over(&caps(&["OVERVIEW"])));
+    }
+
+    #[test]
+    fn test_over_errors() {
+        let response = |line: &str| nntp::NNTPError::UnexpectedResponse(line.to_string());
+        assert!(no_articles_in_range(&response(
+            "423 No articles in that range"
+        )));
+        assert!(no_articles_in_range(&nntp::NNTPError::ArticleUnavailable));
+        assert!(!no_articles_in_range(&response(
+            "412 No newsgroup selected"
+        )));
+        assert!(over_unsupported(&response("500 Unknown command")));
+        assert!(over_unsupported(&response("503 overview not available")));
+        assert!(!over_unsupported(&response(
+            "423 No articles in that range"
+        )));
+        assert!(!over_unsupported(&response("unknown command 500")));
+    }
+}
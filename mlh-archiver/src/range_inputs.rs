@@ -61,6 +61,25 @@ pub fn parse_sequence(s: &str) -> Result<impl Iterator<Item = usize> + use<>, Se
     Ok(final_iterator)
 }
 
+/// Writes sorted numbers in the format read by `parse_sequence`, with consecutive
+/// numbers collapsed into ranges: `[1, 2, 3, 5]` becomes `1-3,5`.
+pub fn format_sequence(numbers: &[usize]) -> String {
+    let mut parts: Vec<String> = vec![];
+    let mut i = 0;
+    while i < numbers.len() {
+        let start = numbers[i];
+        while i + 1 < numbers.len() && numbers[i + 1] == numbers[i] + 1 {
+            i += 1;
+        }
+        parts.push(match numbers[i] == start {
+            true => start.to_string(),
+            false => format!("{start}-{}", numbers[i]),
+        });
+        i += 1;
+    }
+    parts.join(",")
+}
+
 /// Represents the possible errors that can occur during sequence parsing.
 #[derive(Debug, PartialEq)]
 pub enum SequenceParseError {
@@ -74,6 +93,8 @@ pub enum SequenceParseError {
     EmptyPart,
 }
 
+impl std::error::Error for SequenceParseError {}
+
 // Implement the Display trait for nice error messages.
 impl fmt::Display for SequenceParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -237,4 +258,15 @@ mod tests {
             SequenceParseError::InvalidRange("1-2-3".to_string())
         );
     }
+
+    #[test]
+    fn test_format_sequence() {
+        assert_eq!(format_sequence(&[]), "");
+        assert_eq!(format_sequence(&[4]), "4");
+        assert_eq!(format_sequence(&[1, 2, 3, 5, 7, 8]), "1-3,5,7-8");
+        assert_eq!(
+            collect_vec(&format_sequence(&[1, 2, 3, 5, 7, 8])).unwrap(),
+            vec![1, 2, 3, 5, 7, 8]
+        );
+    }
 }
@@ -0,0 +1,201 @@
+//! Politeness limits for the connections to a server.
+//!
+//! Articles and bytes are counted with token buckets, refilled at the configured rate
+//! and holding up to one second of it. A worker waits for a token before each ARTICLE
+//! command, and after reading an article, for the bytes it read.
+//!
+//! The workers share one limiter for the whole server, and each one has its own for
+//! its connection. When the server answers 400 or 502 (usually too many connections),
+//! every worker waits before sending its next command.
+
+use crate::errors;
+use crate::nntp;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// longest wait after the server refused work, however many times it did
+const MAX_BUSY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// rates allowed, None is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub articles_per_second: Option<u32>,
+    pub bytes_per_second: Option<u64>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate,
+            last_refill: now,
+        }
+    }
+
+    /// takes `amount` tokens, returning how long to wait until they are paid back.
+    /// Amounts above the bucket size are allowed, and wait longer
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+
+        self.tokens -= amount;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pause {
+    until: Option<Instant>,
+    // refusals in a row
+    refusals: u32,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    articles: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+    busy_backoff: Duration,
+    pause: Mutex<Pause>,
+}
+
+impl RateLimiter {
+    /// `busy_backoff` is the first wait after the server refused work, doubled at
+    /// each refusal in a row
+    pub fn new(limits: Limits, busy_backoff: Duration) -> RateLimiter {
+        let now = Instant::now();
+        let bucket = |rate: f64| (rate > 0.0).then(|| Mutex::new(TokenBucket::new(rate, now)));
+        RateLimiter {
+            articles: limits
+                .articles_per_second
+                .and_then(|rate| bucket(rate as f64)),
+            bytes: limits.bytes_per_second.and_then(|rate| bucket(rate as f64)),
+            busy_backoff,
+            pause: Mutex::new(Pause::default()),
+        }
+    }
+
+    pub fn unlimited() -> RateLimiter {
+        RateLimiter::new(Limits::default(), Duration::ZERO)
+    }
+
+    /// waits until the server may be sent a command
+    pub fn wait_for_server(&self) {
+        let until = self.pause.lock().unwrap().until;
+        if let Some(wait) = until.map(|until| until.saturating_duration_since(Instant::now()))
+            && !wait.is_zero()
+        {
+            log::info!("Server busy, waiting {}s", wait.as_secs());
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// waits for the server, and for the rate of articles, before an ARTICLE command
+    pub fn wait_for_article(&self) {
+        self.wait_for_server();
+        if let Some(articles) = &self.articles {
+            let wait = articles.lock().unwrap().take(1.0, Instant::now());
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// waits for the rate of bytes, after reading an article of `bytes`
+    pub fn wait_for_bytes(&self, bytes: usize) {
+        if let Some(bucket) = &self.bytes {
+            let wait = bucket.lock().unwrap().take(bytes as f64, Instant::now());
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// the server refused work: every command waits for the backoff. Returns the wait
+    pub fn server_busy(&self) -> Duration {
+        let mut pause = self.pause.lock().unwrap();
+        let backoff = self
+            .busy_backoff
+            .checked_mul(2u32.saturating_pow(pause.refusals))
+            .unwrap_or(MAX_BUSY_BACKOFF)
+            .min(MAX_BUSY_BACKOFF);
+        pause.refusals = pause.refusals.saturating_add(1);
+        let until = Instant::now() + backoff;
+        pause.until = Some(pause.until.map_or(until, |previous| previous.max(until)));
+        backoff
+    }
+
+    /// the server accepted work again
+    pub fn server_ok(&self) {
+        self.pause.lock().unwrap().refusals = 0;
+    }
+}
+
+/// whether an error is the server refusing more work from this client: 400 (service
+/// temporarily unavailable, as with too many connections) or 502 (service unavailable)
+pub fn is_server_busy(error: &nntp::NNTPError) -> bool {
+    matches!(errors::response_code(error), Some(400 | 502))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        // a full bucket holds one second
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, start), Duration::from_millis(500));
+        // refilled at the rate, the debt included
+        assert_eq!(
+            bucket.take(1.0, start + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        // bigger than the bucket
+        assert_eq!(
+            bucket.take(4.0, start + Duration::from_secs(10)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_server_busy_backs_off() {
+        let limiter = RateLimiter::new(Limits::default(), Duration::from_secs(10));
+        assert_eq!(limiter.server_busy(), Duration::from_secs(10));
+        assert_eq!(limiter.server_busy(), Duration::from_secs(20));
+        limiter.server_ok();
+        assert_eq!(limiter.server_busy(), Duration::from_secs(10));
+        for _ in 0..20 {
+            limiter.server_busy();
+        }
+        assert_eq!(limiter.server_busy(), MAX_BUSY_BACKOFF);
+    }
+
+    #[test]
+    fn test_is_server_busy() {
+        let response = |line: &str| nntp::NNTPError::UnexpectedResponse(line.to_string());
+        assert!(is_server_busy(&response(
+            "400 too many connections from your host"
+        )));
+        assert!(is_server_busy(&response("502 access denied")));
+        assert!(!is_server_busy(&response(
+            "423 no article with that number"
+        )));
+        // only the status code counts, not the numbers in the text
+        assert!(!is_server_busy(&response("480 400 connections max")));
+        assert!(!is_server_busy(&nntp::NNTPError::ArticleUnavailable));
+    }
+}
@@ -0,0 +1,360 @@
+//! When each group is checked next.
+//!
+//! The time between two checks of a group adapts to how often new articles arrive:
+//! busy groups are checked every `poll_interval_min` seconds, groups without new
+//! articles less and less often, up to `poll_interval_max`. Groups with their own
+//! `poll_interval` are checked at that fixed interval.
+//!
+//! A group whose check failed is checked again after a minute, then after twice as
+//! long at each new failure, up to `poll_interval_max`.
+//!
+//! The schedule is kept in the output folder, so a restart does not check every
+//! group at once:
+//!
+//! ```text
+//! {output_dir}/__schedule.yml
+//! ```
+
+use crate::config::{AppConfig, GroupConfig};
+use crate::file_utils;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const SCHEDULE_FILE_NAME: &str = "__schedule.yml";
+/// the interval aims at one new article per check
+const ARTICLES_PER_CHECK: f64 = 1.0;
+/// weight of the last check in the arrival rate, the previous ones share the rest
+const RATE_WEIGHT: f64 = 0.5;
+/// delay before checking again a group whose check failed, doubled at each new failure
+const FIRST_RETRY: TimeDelta = TimeDelta::minutes(1);
+/// longer intervals are shortened to this
+const LONGEST_INTERVAL: TimeDelta = TimeDelta::days(365);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct GroupSchedule {
+    pub last_check: Option<DateTime<Utc>>,
+    pub next_check: DateTime<Utc>,
+    /// new articles per second, averaged over the previous checks
+    pub rate: Option<f64>,
+    /// failed checks since the last successful one
+    #[serde(default)]
+    pub failures: u32,
+}
+
+/// Groups ordered by their next check
+pub struct Schedule {
+    min_interval: TimeDelta,
+    max_interval: TimeDelta,
+    // not saved if None
+    path: Option<PathBuf>,
+    saved: BTreeMap<String, GroupSchedule>,
+    // the groups, and when they are checked next. None while they are being checked
+    groups: Vec<(GroupConfig, Option<DateTime<Utc>>)>,
+    // next check, and index in groups
+    queue: BTreeSet<(DateTime<Utc>, usize)>,
+}
+
+impl Schedule {
+    /// a schedule that is not saved
+    pub fn new(min_interval: Duration, max_interval: Duration) -> Schedule {
+        let min_interval = to_interval(min_interval);
+        let max_interval = to_interval(max_interval);
+        Schedule {
+            min_interval,
+            max_interval: max_interval.max(min_interval),
+            path: None,
+            saved: BTreeMap::new(),
+            groups: vec![],
+            queue: BTreeSet::new(),
+        }
+    }
+
+    /// the schedule saved in the output folder, or an empty one if there is none
+    pub fn load(app_config: &AppConfig) -> io::Result<Schedule> {
+        let mut schedule = Schedule::new(
+            Duration::from_secs(app_config.poll_interval_min),
+            Duration::from_secs(app_config.poll_interval_max),
+        );
+        let path = Path::new(&app_config.output_dir).join(SCHEDULE_FILE_NAME);
+        if path.exists() {
+            schedule.saved = file_utils::read_yaml(&path.to_string_lossy())?;
+        }
+        schedule.path = Some(path);
+        Ok(schedule)
+    }
+
+    /// queues a group at its saved next check, or now if it was never checked.
+    /// Groups with a range are always checked now
+    pub fn add(&mut self, group: GroupConfig, now: DateTime<Utc>) {
+        if self
+            .groups
+            .iter()
+            .any(|(known, _)| known.name == group.name)
+        {
+            return;
+        }
+        let next_check = match (&group.range, self.saved.get(&group.name)) {
+            (None, Some(saved)) => saved.next_check,
+            _ => now,
+        };
+        self.groups.push((group, None));
+        self.queue(self.groups.len() - 1, next_check);
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = &GroupConfig> {
+        self.groups.iter().map(|(group, _)| group)
+    }
+
+    /// the earliest next check, if any group is queued
+    pub fn next_check(&self) -> Option<DateTime<Utc>> {
+        self.queue.first().map(|(next_check, _)| *next_check)
+    }
+
+    /// removes the groups due at `now` from the queue, by descending priority.
+    ///
+    /// They are queued again once their check is reported (see `checked` and `failed`),
+    /// so a group is never checked by two workers at once
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> Vec<GroupConfig> {
+        let mut due = vec![];
+        while let Some((next_check, index)) = self.queue.first().copied()
+            && next_check <= now
+        {
+            self.queue.remove(&(next_check, index));
+            self.groups[index].1 = None;
+            due.push(index);
+        }
+        // stable sort, groups of the same priority keep the order they were due in
+        due.sort_by_key(|index| Reverse(self.groups[*index].0.priority));
+        due.into_iter()
+            .map(|index| self.groups[index].0.clone())
+            .collect()
+    }
+
+    /// updates the arrival rate of a group after a check that found `new_articles`,
+    /// and queues its next check
+    pub fn checked(
+        &mut self,
+        group_name: &str,
+        new_articles: usize,
+        now: DateTime<Utc>,
+    ) -> io::Result<()> {
+        let Some(index) = self.followed_group(group_name) else {
+            return Ok(());
+        };
+
+        let saved = self.saved_group(group_name, now);
+        // the first check may read a backlog, not what arrived since
+        if let Some(last_check) = saved.last_check {
+            let elapsed = (now - last_check).num_seconds();
+            if elapsed > 0 {
+                let observed = new_articles as f64 / elapsed as f64;
+                saved.rate = Some(match saved.rate {
+                    Some(rate) => RATE_WEIGHT * observed + (1.0 - RATE_WEIGHT) * rate,
+                    None => observed,
+                });
+            }
+        }
+        saved.last_check = Some(now);
+        saved.failures = 0;
+
+        let next_check = now + self.interval(&self.groups[index].0);
+        self.saved.get_mut(group_name).unwrap().next_check = next_check;
+        self.queue(index, next_check);
+        self.save()
+    }
+
+    /// queues the next check of a group whose check failed, sooner than a regular
+    /// one at first. Returns the failures in a row
+    pub fn failed(&mut self, group_name: &str, now: DateTime<Utc>) -> io::Result<u32> {
+        let Some(index) = self.followed_group(group_name) else {
+            return Ok(0);
+        };
+
+        let saved = self.saved_group(group_name, now);
+        saved.failures = saved.failures.saturating_add(1);
+        let failures = saved.failures;
+        let retry = (1..failures)
+            .try_fold(FIRST_RETRY, |retry, _| retry.checked_mul(2))
+            .unwrap_or(self.max_interval)
+            .min(self.max_interval);
+
+        let next_check = now + retry;
+        self.saved.get_mut(group_name).unwrap().next_check = next_check;
+        self.queue(index, next_check);
+        self.save()?;
+        Ok(failures)
+    }
+
+    /// time until the next check of a group
+    pub fn interval(&self, group: &GroupConfig) -> TimeDelta {
+        if let Some(poll_interval) = group.poll_interval {
+            return to_interval(Duration::from_secs(poll_interval));
+        }
+        match self.saved.get(&group.name).and_then(|saved| saved.rate) {
+            Some(rate) if rate > 0.0 => {
+                let seconds =
+                    (ARTICLES_PER_CHECK / rate).min(LONGEST_INTERVAL.num_seconds() as f64);
+                TimeDelta::seconds(seconds as i64).clamp(self.min_interval, self.max_interval)
+            }
+            Some(_) => self.max_interval,
+            None => self.min_interval,
+        }
+    }
+
+    /// index of a group checked repeatedly, without a range
+    fn followed_group(&self, group_name: &str) -> Option<usize> {
+        self.groups
+            .iter()
+            .position(|(group, _)| group.name == group_name && group.range.is_none())
+    }
+
+    fn saved_group(&mut self, group_name: &str, now: DateTime<Utc>) -> &mut GroupSchedule {
+        self.saved
+            .entry(group_name.to_string())
+            .or_insert(GroupSchedule {
+                last_check: None,
+                next_check: now,
+                rate: None,
+                failures: 0,
+            })
+    }
+
+    fn queue(&mut self, index: usize, next_check: DateTime<Utc>) {
+        if let Some(previous) = self.groups[index].1.replace(next_check) {
+            self.queue.remove(&(previous, index));
+        }
+        self.queue.insert((next_check, index));
+    }
+
+    fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => file_utils::write_yaml(&path.to_string_lossy(), &self.saved),
+            None => Ok(()),
+        }
+    }
+}
+
+fn to_interval(duration: Duration) -> TimeDelta {
+    TimeDelta::from_std(duration)
+        .unwrap_or(LONGEST_INTERVAL)
+        .min(LONGEST_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn time(time: &str) -> DateTime<Utc> {
+        format!("2024-01-31T{time}Z").parse().unwrap()
+    }
+
+    #[test]
+    fn test_take_due_by_priority() {
+        let mut schedule = Schedule::new(5 * MINUTE, 60 * MINUTE);
+        let mut urgent = GroupConfig::new("urgent");
+        urgent.priority = 5;
+        let mut once = GroupConfig::new("once");
+        once.range = Some("1-10".to_string());
+        schedule.add(GroupConfig::new("a"), time("09:00:00"));
+        schedule.add(urgent, time("09:00:00"));
+        schedule.add(once, time("09:00:00"));
+        schedule.add(GroupConfig::new("later"), time("09:10:00"));
+
+        let names = |groups: Vec<GroupConfig>| -> Vec<String> {
+            groups.into_iter().map(|group| group.name).collect()
+        };
+        assert_eq!(
+            names(schedule.take_due(time("09:00:00"))),
+            vec!["urgent", "a", "once"]
+        );
+        // the groups being checked are not queued
+        assert_eq!(schedule.next_check(), Some(time("09:10:00")));
+
+        // the groups without a range are queued again once checked
+        schedule.checked("urgent", 0, time("09:01:00")).unwrap();
+        schedule.checked("a", 0, time("09:01:00")).unwrap();
+        schedule.checked("once", 0, time("09:01:00")).unwrap();
+        assert_eq!(schedule.next_check(), Some(time("09:06:00")));
+        assert_eq!(
+            names(schedule.take_due(time("09:10:00"))),
+            vec!["urgent", "a", "later"]
+        );
+    }
+
+    #[test]
+    fn test_interval_adapts() {
+        let mut schedule = Schedule::new(5 * MINUTE, 60 * MINUTE);
+        let group = GroupConfig::new("a");
+        schedule.add(group.clone(), time("09:00:00"));
+        schedule.take_due(time("09:00:00"));
+
+        // a first check, reading the backlog
+        schedule.checked("a", 1000, time("09:00:00")).unwrap();
+        assert_eq!(schedule.interval(&group), TimeDelta::minutes(5));
+        // one article every 10 minutes
+        schedule.checked("a", 1, time("09:10:00")).unwrap();
+        assert_eq!(schedule.interval(&group), TimeDelta::minutes(10));
+        assert_eq!(schedule.next_check(), Some(time("09:20:00")));
+        // without new articles, the rate is halved
+        schedule.checked("a", 0, time("09:20:00")).unwrap();
+        assert_eq!(schedule.interval(&group), TimeDelta::minutes(20));
+        // busy groups are checked every poll_interval_min
+        schedule.checked("a", 100, time("09:40:00")).unwrap();
+        assert_eq!(schedule.interval(&group), TimeDelta::minutes(5));
+
+        let mut fixed = GroupConfig::new("fixed");
+        fixed.poll_interval = Some(600);
+        assert_eq!(schedule.interval(&fixed), TimeDelta::minutes(10));
+    }
+
+    #[test]
+    fn test_failed_checks_back_off() {
+        let mut schedule = Schedule::new(5 * MINUTE, 60 * MINUTE);
+        schedule.add(GroupConfig::new("a"), time("09:00:00"));
+        schedule.take_due(time("09:00:00"));
+
+        assert_eq!(schedule.failed("a", time("09:00:00")).unwrap(), 1);
+        assert_eq!(schedule.next_check(), Some(time("09:01:00")));
+        schedule.take_due(time("09:01:00"));
+        assert_eq!(schedule.failed("a", time("09:01:00")).unwrap(), 2);
+        assert_eq!(schedule.next_check(), Some(time("09:03:00")));
+        for _ in 0..10 {
+            schedule.take_due(time("10:00:00"));
+            schedule.failed("a", time("10:00:00")).unwrap();
+        }
+        // up to poll_interval_max
+        assert_eq!(schedule.next_check(), Some(time("11:00:00")));
+
+        schedule.take_due(time("11:00:00"));
+        schedule.checked("a", 0, time("11:00:00")).unwrap();
+        schedule.take_due(time("11:05:00"));
+        assert_eq!(schedule.failed("a", time("11:05:00")).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_schedule_is_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app_config = AppConfig::defaults();
+        app_config.output_dir = dir.path().to_string_lossy().to_string();
+
+        let mut schedule = Schedule::load(&app_config).unwrap();
+        schedule.add(GroupConfig::new("a"), time("09:00:00"));
+        schedule.take_due(time("09:00:00"));
+        schedule.checked("a", 0, time("09:00:00")).unwrap();
+
+        // after a restart, the group waits for its next check
+        let mut schedule = Schedule::load(&app_config).unwrap();
+        schedule.add(GroupConfig::new("a"), time("09:01:00"));
+        schedule.add(GroupConfig::new("b"), time("09:01:00"));
+        assert_eq!(schedule.take_due(time("09:01:00")).len(), 1);
+        assert_eq!(schedule.next_check(), Some(time("09:05:00")));
+    }
+}
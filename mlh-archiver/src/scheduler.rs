@@ -1,35 +1,87 @@
+use crate::config::GroupConfig;
+use crate::connection::{ConnectionConfig, Connector};
+use crate::discovery::GroupDiscovery;
 use crate::errors;
-use crate::worker;
-use crossbeam_channel::bounded;
-use std::thread;
-use std::{sync::Arc, time::Duration};
+use crate::rate_limit::{self, Limits, RateLimiter};
+use crate::schedule::Schedule;
+use crate::signals::Signal;
+use crate::storage::Storage;
+use crate::worker::{self, TaskReport, WorkerGroupResult};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{bounded, select, unbounded};
+use std::thread::{self, JoinHandle};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 // intervals in seconds
 const INTERVAL_BETWEEN_RESCANS: usize = 60 * 60; // 1h
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+/// how often the workers are checked while waiting for their reports
+const REPORT_WAIT: Duration = Duration::from_secs(1);
 
 pub struct Scheduler {
-    hostname: String,
-    port: u16,
-    base_output_path: String,
+    connection: ConnectionConfig,
+    // opens the sessions of the workers
+    connector: Arc<dyn Connector>,
+    storage: Arc<dyn Storage>,
+    pipeline_depth: u8,
+    error_retry_max_age_days: u32,
     nthreds: u8,
     loop_groups: bool,
-    tasklist: Arc<Vec<String>>,
+    tasklist: Arc<Vec<GroupConfig>>,
+    // when each group is checked next
+    schedule: Schedule,
+    // finds the groups created while running, if enabled
+    discovery: Option<GroupDiscovery>,
+    // stops dispatching groups, and the workers after their current article
+    signals: crossbeam_channel::Receiver<Signal>,
+    shutdown_timeout: Duration,
+    stopping: Arc<AtomicBool>,
     task_channel: (
-        crossbeam_channel::Sender<String>,
-        crossbeam_channel::Receiver<String>,
+        crossbeam_channel::Sender<GroupConfig>,
+        crossbeam_channel::Receiver<GroupConfig>,
     ),
+    results_channel: (
+        crossbeam_channel::Sender<TaskReport>,
+        crossbeam_channel::Receiver<TaskReport>,
+    ),
+    // shared by the workers, and applied to each connection
+    server_limits: Arc<RateLimiter>,
+    connection_limits: Limits,
+    // what the workers reported for each group
+    summary: BTreeMap<String, GroupSummary>,
+    // groups sent to the workers, not reported yet
+    in_flight: HashSet<String>,
+}
+
+/// the tasks of one group reported by the workers
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GroupSummary {
+    pub checks: usize,
+    pub new_articles: usize,
+    pub failures: usize,
+    pub last_error: Option<String>,
+    /// time spent by the workers on the group
+    pub duration: Duration,
 }
 
 impl Scheduler {
     pub fn new(
-        hostname: String,
-        port: u16,
-        base_output_path: String,
+        connection: ConnectionConfig,
+        storage: Arc<dyn Storage>,
+        pipeline_depth: u8,
+        error_retry_max_age_days: u32,
         nthreds: u8,
         loop_groups: bool,
-        groups: Vec<String>,
+        groups: Vec<GroupConfig>,
     ) -> Scheduler {
-        let mut tasklist: Vec<String> = Vec::with_capacity(groups.len());
+        let mut tasklist: Vec<GroupConfig> = Vec::with_capacity(groups.len());
 
         // Schedule all groups for check to the next second
         for group in groups {
@@ -37,17 +89,82 @@ impl Scheduler {
         }
 
         Scheduler {
-            hostname,
-            port,
-            base_output_path,
+            connector: Arc::new(connection.clone()),
+            connection,
+            storage,
+            pipeline_depth,
+            error_retry_max_age_days,
             nthreds,
             loop_groups,
             tasklist: Arc::new(tasklist),
-            task_channel: bounded::<String>(nthreds as usize),
+            schedule: Schedule::new(
+                Duration::from_secs(INTERVAL_BETWEEN_RESCANS as u64),
+                Duration::from_secs(INTERVAL_BETWEEN_RESCANS as u64),
+            ),
+            discovery: None,
+            signals: crossbeam_channel::never(),
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            stopping: Arc::new(AtomicBool::new(false)),
+            task_channel: bounded::<GroupConfig>(nthreds as usize),
+            results_channel: unbounded::<TaskReport>(),
+            server_limits: Arc::new(RateLimiter::unlimited()),
+            connection_limits: Limits::default(),
+            summary: BTreeMap::new(),
+            in_flight: HashSet::new(),
         }
     }
 
-    pub fn run(&mut self) -> crate::Result<()> {
+    /// checks the groups at intervals adapted to their activity. Defaults to a fixed
+    /// hour between checks
+    pub fn with_schedule(mut self, schedule: Schedule) -> Scheduler {
+        self.schedule = schedule;
+        self
+    }
+
+    /// limits the rate of the whole server and of each connection, and makes every
+    /// worker wait `busy_backoff` when the server refuses work
+    pub fn with_rate_limits(
+        mut self,
+        server_limits: Limits,
+        connection_limits: Limits,
+        busy_backoff: Duration,
+    ) -> Scheduler {
+        self.server_limits = Arc::new(RateLimiter::new(server_limits, busy_backoff));
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    /// opens the sessions of the workers with `connector`, instead of the connection
+    /// configuration
+    pub fn with_connector(mut self, connector: Arc<dyn Connector>) -> Scheduler {
+        self.connector = connector;
+        self
+    }
+
+    /// adds the groups created on the server while running
+    pub fn with_discovery(mut self, discovery: GroupDiscovery) -> Scheduler {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    /// stops on the signals received, waiting up to shutdown_timeout for the workers
+    pub fn with_signals(
+        mut self,
+        signals: crossbeam_channel::Receiver<Signal>,
+        shutdown_timeout: Duration,
+    ) -> Scheduler {
+        self.signals = signals;
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// dispatches the groups to the workers, until a signal is received. Returns that
+    /// signal.
+    ///
+    /// If loop_groups is off, every group is sent once, and the workers are stopped
+    /// once they all reported. See `summary` for the results
+    pub fn run(&mut self) -> crate::Result<Option<Signal>> {
+        let mut workers: Vec<JoinHandle<()>> = vec![];
         // start worker threads
         for id in 0..self.nthreds {
             log::debug!("Stating worker thread {id}");
@@ -56,66 +173,390 @@ impl Scheduler {
 
             let mut worker = worker::Worker::new(
                 id,
-                self.hostname.clone(),
-                self.port,
-                self.base_output_path.clone(),
+                self.connector(),
+                self.storage.clone(),
+                self.pipeline_depth,
+                self.error_retry_max_age_days,
                 receiver,
-            );
+                self.stopping.clone(),
+            )
+            .with_results(self.results_channel.0.clone())
+            .with_rate_limits(self.server_limits.clone(), self.connection_limits);
+            let stopping = self.stopping.clone();
             // Spin up another thread
-            thread::spawn(move || {
+            workers.push(thread::spawn(move || {
                 loop {
                     match worker.run() {
                         Ok(_) => {
                             log::info!("Worker {id} finished");
                             break;
                         }
+                        Err(errors::Error::Authentication(err)) => {
+                            // credentials will not fix themselves, stop instead of looping
+                            log::error!("Worker {id} stopped, could not authenticate: {err}");
+                            break;
+                        }
+                        Err(err) if stopping.load(Ordering::SeqCst) => {
+                            log::warn!("Worker {id} stopped with an error : {err}");
+                            break;
+                        }
                         Err(err) => {
-                            // TODO: use this to reschedule
+                            // failed while reconnecting. Failed tasks are reported and rescheduled
                             log::warn!("Worker {id} returned an error : {err}");
                             std::thread::sleep(Duration::from_secs(1));
                         }
                     };
                 }
-            });
+            }));
             // space out thread creation (to prevent multiple connections opening at once)
             std::thread::sleep(Duration::from_secs(2));
         }
 
-        // TODO: move this to other thread, handle OS signlas in the original thread instead
-        // thread::spawn(move || {
+        // groups with a range are only sent once
+        for group in self.tasklist.iter() {
+            self.schedule.add(group.clone(), Utc::now());
+        }
+        let mut next_discovery = self
+            .discovery
+            .as_ref()
+            .map(|discovery| Instant::now() + discovery.interval());
         loop {
-            for group_name in self.tasklist.iter() {
-                self.task_channel.0.send(group_name.clone()).unwrap();
+            while let Ok(report) = self.results_channel.1.try_recv() {
+                self.task_reported(report);
+            }
+
+            // without loop_groups, every group is checked, even if it is not due yet
+            let until = match self.loop_groups {
+                true => Utc::now(),
+                false => DateTime::<Utc>::MAX_UTC,
+            };
+            for group in self.schedule.take_due(until) {
+                let group_name = group.name.clone();
+                // waits for a free worker, unless a signal comes first
+                select! {
+                    send(self.task_channel.0, group) -> result => result.unwrap(),
+                    recv(self.signals) -> signal => return self.stop(workers, signal.ok()),
+                }
+                self.in_flight.insert(group_name);
             }
+
             if !self.loop_groups {
-                return Ok(());
+                return self.wait_for_completion(workers);
+            }
+
+            if next_discovery.is_some_and(|next_discovery| next_discovery <= Instant::now()) {
+                for group in self.discover_groups() {
+                    self.schedule.add(group, Utc::now());
+                }
+                next_discovery = self
+                    .discovery
+                    .as_ref()
+                    .map(|discovery| Instant::now() + discovery.interval());
             }
+
             // interval between checks to task list
-            std::thread::sleep(Duration::from_secs(INTERVAL_BETWEEN_RESCANS as u64));
+            let next_check = self
+                .schedule
+                .next_check()
+                .map(|next_check| (next_check - Utc::now()).to_std().unwrap_or(Duration::ZERO));
+            let next_check = next_check
+                .into_iter()
+                .chain(next_discovery.map(|next| next.saturating_duration_since(Instant::now())))
+                .min();
+            let timeout = match (next_check, self.in_flight.is_empty()) {
+                (Some(timeout), _) => timeout,
+                // the groups being checked are queued again once reported
+                (None, false) => REPORT_WAIT,
+                (None, true) => {
+                    self.log_summary();
+                    return Ok(None);
+                }
+            };
+            select! {
+                recv(self.signals) -> signal => match signal {
+                    Ok(signal) => return self.stop(workers, Some(signal)),
+                    Err(_) => {
+                        log::warn!("Signals are not handled anymore");
+                        self.signals = crossbeam_channel::never();
+                    }
+                },
+                recv(self.results_channel.1) -> report => {
+                    if let Ok(report) = report {
+                        self.task_reported(report);
+                    }
+                },
+                default(timeout) => {},
+            }
         }
-        // });
     }
 
-    // run range does not keep track of lists, just run them once for the defined range
-    pub fn run_range(&mut self, range: impl Iterator<Item = usize>) -> crate::Result<()> {
+    /// schedules the next check of a group from its last one: after an interval
+    /// adapted to the articles found, or sooner if it failed
+    fn task_reported(&mut self, report: TaskReport) {
+        let now = Utc::now();
+        self.in_flight.remove(report.result.group_name());
+        let summary = self
+            .summary
+            .entry(report.result.group_name().to_string())
+            .or_default();
+        summary.checks += 1;
+        summary.duration += report.duration;
+
+        let saved = match &report.result {
+            WorkerGroupResult::Ok(group_name, num_emails) => {
+                summary.new_articles += num_emails;
+                self.schedule.checked(group_name, *num_emails, now)
+            }
+            WorkerGroupResult::NoNews(group_name) => self.schedule.checked(group_name, 0, now),
+            WorkerGroupResult::Failed(group_name, kind, error) => {
+                summary.failures += 1;
+                summary.last_error = Some(error.clone());
+                self.schedule.failed(group_name, now).map(|failures| {
+                    log::warn!(
+                        "W{}: checking {group_name} failed ({kind:?} error), {failures} times in a row",
+                        report.worker
+                    );
+                })
+            }
+        };
+        if let Err(e) = saved {
+            log::warn!("Failed saving the schedule: {e}");
+        }
+    }
+
+    /// what the workers reported for each group, since the start
+    pub fn summary(&self) -> &BTreeMap<String, GroupSummary> {
+        &self.summary
+    }
+
+    fn log_summary(&self) {
+        for (group_name, summary) in self.summary.iter() {
+            log::info!(
+                "{group_name}: {} new articles in {} checks ({} failed), {:.1}s",
+                summary.new_articles,
+                summary.checks,
+                summary.failures,
+                summary.duration.as_secs_f64()
+            );
+        }
+    }
+
+    /// waits for the reports of the groups sent, then stops the workers
+    fn wait_for_completion(
+        &mut self,
+        workers: Vec<JoinHandle<()>>,
+    ) -> crate::Result<Option<Signal>> {
+        while !self.in_flight.is_empty() {
+            if workers.iter().all(|worker| worker.is_finished()) {
+                log::error!("All workers stopped before checking every group");
+                break;
+            }
+            select! {
+                recv(self.signals) -> signal => match signal {
+                    Ok(signal) => return self.stop(workers, Some(signal)),
+                    Err(_) => self.signals = crossbeam_channel::never(),
+                },
+                recv(self.results_channel.1) -> report => {
+                    if let Ok(report) = report {
+                        self.task_reported(report);
+                    }
+                },
+                default(REPORT_WAIT) => {},
+            }
+        }
+        self.not_reported();
+
+        // closing the channel stops the idle workers, and the ones waiting to reconnect
+        // stop too, as nothing is left to read
+        self.stopping.store(true, Ordering::SeqCst);
+        self.task_channel = bounded::<GroupConfig>(0);
+        self.join_workers(workers, false);
+        self.log_summary();
+        Ok(None)
+    }
+
+    /// stops dispatching, and waits for the workers to finish their current article,
+    /// write its read status and close their connection
+    fn stop(
+        &mut self,
+        workers: Vec<JoinHandle<()>>,
+        signal: Option<Signal>,
+    ) -> crate::Result<Option<Signal>> {
+        log::info!(
+            "Stopping ({signal:?}), waiting up to {}s for the workers",
+            self.shutdown_timeout.as_secs()
+        );
+        self.stopping.store(true, Ordering::SeqCst);
+        // closing the channel wakes up the idle workers
+        self.task_channel = bounded::<GroupConfig>(0);
+        // the new workers of a reload must not share the server with the old ones
+        self.join_workers(workers, signal == Some(Signal::Reload));
+
+        while let Ok(report) = self.results_channel.1.try_recv() {
+            self.task_reported(report);
+        }
+        self.not_reported();
+        self.log_summary();
+        Ok(signal)
+    }
+
+    /// counts the groups sent but never reported as failed
+    fn not_reported(&mut self) {
+        for group_name in self.in_flight.drain() {
+            let summary = self.summary.entry(group_name).or_default();
+            summary.failures += 1;
+            summary.last_error = Some("not checked, the workers stopped".to_string());
+        }
+    }
+
+    /// waits up to shutdown_timeout for the workers to finish, or until they all do
+    fn join_workers(&self, workers: Vec<JoinHandle<()>>, wait_all: bool) {
+        let deadline = Instant::now() + self.shutdown_timeout;
+        while workers.iter().any(|worker| !worker.is_finished()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let mut running = 0;
+        for worker in workers {
+            match worker.is_finished() {
+                true => {
+                    let _ = worker.join();
+                }
+                false if wait_all => {
+                    log::warn!("A worker did not stop in time, waiting for it before reloading");
+                    let _ = worker.join();
+                }
+                false => running += 1,
+            }
+        }
+        if running > 0 {
+            log::warn!("{running} workers did not stop in time, leaving them behind");
+        } else {
+            log::info!("All workers stopped");
+        }
+    }
+
+    fn connector(&self) -> Arc<dyn Connector> {
+        self.connector.clone()
+    }
+
+    /// the groups created on the server since the last check, that should be read
+    fn discover_groups(&mut self) -> Vec<GroupConfig> {
+        let Some(discovery) = self.discovery.as_mut() else {
+            return vec![];
+        };
+        let known: HashSet<String> = self
+            .schedule
+            .groups()
+            .map(|group| group.name.clone())
+            .collect();
+
+        // NEWGROUPS waits for the server like the commands of the workers
+        self.server_limits.wait_for_server();
+        let result = worker::connect_to_nntp(&self.connection).and_then(|mut nntp_stream| {
+            let groups = discovery.discover(&mut nntp_stream, &known);
+            let _ = nntp_stream.quit();
+            groups
+        });
+        match result {
+            Ok(groups) => {
+                for group in groups.iter() {
+                    log::info!("New group {} found on the server, reading it", group.name);
+                }
+                groups
+            }
+            Err(e) => {
+                log::warn!("Failed checking for new groups: {e}");
+                if let errors::Error::NNTP(error) = &e
+                    && rate_limit::is_server_busy(error)
+                {
+                    self.server_limits.server_busy();
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// fetches a range of articles of one group, once
+    pub fn run_range(
+        &mut self,
+        group_name: &str,
+        range: impl Iterator<Item = usize>,
+    ) -> crate::Result<()> {
         let receiver = self.task_channel.1.clone();
         let mut worker = worker::Worker::new(
             0,
-            self.hostname.clone(),
-            self.port,
-            self.base_output_path.clone(),
+            self.connector(),
+            self.storage.clone(),
+            self.pipeline_depth,
+            self.error_retry_max_age_days,
             receiver,
-        );
+            self.stopping.clone(),
+        )
+        .with_rate_limits(self.server_limits.clone(), self.connection_limits);
+        worker.connect()?;
 
-        match self.tasklist.first() {
-            Some(group_name) => {
-                // TODO: map this error
-                worker.handle_group_range(group_name.clone(), range)?;
-                Ok(())
-            }
-            None => Err(errors::Error::Unknown),
-        }?;
+        let num_emails_read = worker.handle_group_range(group_name.to_string(), range)?;
+        log::info!("Fetched {num_emails_read} articles in {group_name}");
+        return Ok(());
+    }
+
+    /// fetches the articles missing in each group, up to its read status, once
+    pub fn run_backfill(&mut self) -> crate::Result<()> {
+        let receiver = self.task_channel.1.clone();
+        let mut worker = worker::Worker::new(
+            0,
+            self.connector(),
+            self.storage.clone(),
+            self.pipeline_depth,
+            self.error_retry_max_age_days,
+            receiver,
+            self.stopping.clone(),
+        )
+        .with_rate_limits(self.server_limits.clone(), self.connection_limits);
+        worker.connect()?;
 
+        for group in self.tasklist.iter() {
+            let num_emails_read = worker.backfill_group(group.name.clone())?;
+            log::info!("Backfilled {num_emails_read} articles in {}", group.name);
+        }
         return Ok(());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::worker::tests::{FakeConnector, FakeServer, GROUP};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_run_waits_for_every_report() {
+        let server = Arc::new(Mutex::new(FakeServer::new(1..=3)));
+        let storage = Arc::new(MemoryStorage::new());
+        let groups = vec![GroupConfig::new(GROUP), GroupConfig::new("missing.group")];
+        let mut scheduler = Scheduler::new(
+            ConnectionConfig::default(),
+            storage.clone(),
+            1,
+            30,
+            1,
+            false,
+            groups,
+        )
+        .with_connector(Arc::new(FakeConnector(server.clone())));
+
+        // the worker waiting to reconnect after the failure is stopped too
+        let started = Instant::now();
+        assert_eq!(scheduler.run().unwrap(), None);
+        assert!(started.elapsed() < Duration::from_secs(30));
+
+        let summary = scheduler.summary();
+        assert_eq!(summary[GROUP].new_articles, 3);
+        assert_eq!(summary[GROUP].failures, 0);
+        assert_eq!(summary["missing.group"].checks, 1);
+        assert_eq!(summary["missing.group"].failures, 1);
+        assert_eq!(storage.list_articles(GROUP).unwrap(), vec![1, 2, 3]);
+        assert_eq!(server.lock().unwrap().sessions, 1);
+    }
+}
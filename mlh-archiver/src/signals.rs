@@ -0,0 +1,46 @@
+//! OS signals handled while syncing.
+//!
+//! SIGINT and SIGTERM stop the archiver gracefully, SIGHUP reloads the configuration.
+//! A second SIGINT or SIGTERM exits at once, without waiting for the workers.
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::{io, thread};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT or SIGTERM
+    Shutdown,
+    /// SIGHUP
+    Reload,
+}
+
+/// exit status after a second SIGINT or SIGTERM
+const FORCED_EXIT_STATUS: i32 = 130;
+
+/// starts a thread forwarding the signals received by the process
+pub fn listen() -> io::Result<crossbeam_channel::Receiver<Signal>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    thread::spawn(move || {
+        let mut shutdowns = 0;
+        for signal in signals.forever() {
+            let signal = match signal {
+                SIGHUP => Signal::Reload,
+                _ => Signal::Shutdown,
+            };
+            if signal == Signal::Shutdown {
+                shutdowns += 1;
+                if shutdowns > 1 {
+                    log::warn!("Stop requested again, exiting without waiting for the workers");
+                    std::process::exit(FORCED_EXIT_STATUS);
+                }
+            }
+            if sender.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
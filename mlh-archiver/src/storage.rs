@@ -0,0 +1,191 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use std::io;
+
+mod files;
+mod maildir;
+mod mbox_mirror;
+mod memory;
+mod per_group;
+mod public_inbox;
+mod sqlite;
+
+pub use files::FileStorage;
+pub use maildir::MaildirStorage;
+pub use mbox_mirror::MboxMirror;
+pub use memory::MemoryStorage;
+pub use per_group::PerGroupStorage;
+pub use public_inbox::PublicInboxStorage;
+pub use sqlite::SqliteStorage;
+
+/// Where archived articles and the progress of each group are kept.
+///
+/// The fetch logic in `Worker` only talks to this trait, so the archive layout
+/// can change without touching it.
+pub trait Storage: Send + Sync {
+    /// stores the raw lines of an article
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()>;
+
+    /// raw lines of a stored article, or `None` if it is not stored
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>>;
+
+    /// last article read from the group. A group never read starts at 0
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus>;
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()>;
+
+    /// records an article the server could not provide, or one more failed attempt
+    /// if it was already recorded
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()>;
+
+    /// articles recorded with `record_error`, by ascending article number
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>>;
+
+    /// forgets a recorded error, once the article was stored
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()>;
+
+    /// numbers of the articles stored for the group, in ascending order
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>>;
+
+    /// the articles of a group left incomplete by an interrupted write, set aside to be
+    /// fetched again. They stay listed until stored again, or recorded as an error
+    ///
+    /// Storages whose writes cannot be interrupted halfway find nothing.
+    fn damaged_articles(&self, _group: &str) -> io::Result<Vec<usize>> {
+        Ok(vec![])
+    }
+
+    /// groups with something stored, sorted by name
+    fn list_groups(&self) -> io::Result<Vec<String>>;
+
+    /// stores an article and moves the read status forward, atomically when the storage can
+    ///
+    /// By default the article is stored first, so a crash in between only means
+    /// the article is fetched again.
+    fn put_article_and_read_status(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+        read_status: &ReadStatus,
+    ) -> io::Result<()> {
+        self.put_article(group, article_number, raw_article)?;
+        self.put_read_status(group, read_status)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadStatus {
+    pub last_email: usize,
+}
+
+// delay before the first retry of an unavailable article, doubled after each attempt
+const ERROR_RETRY_BASE_DELAY: TimeDelta = TimeDelta::hours(1);
+// the delay stops doubling after this many attempts (about 10 days)
+const ERROR_RETRY_MAX_DOUBLINGS: u32 = 8;
+
+/// An article the server could not provide, tried again later
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArticleError {
+    pub article_number: usize,
+    /// error of the last attempt
+    pub error: String,
+    pub attempts: u32,
+    pub first_tried: DateTime<Utc>,
+    pub last_tried: DateTime<Utc>,
+}
+
+impl ArticleError {
+    pub fn new(article_number: usize, error: &str, now: DateTime<Utc>) -> ArticleError {
+        ArticleError {
+            article_number,
+            error: error.to_string(),
+            attempts: 1,
+            first_tried: now,
+            last_tried: now,
+        }
+    }
+
+    /// counts one more failed attempt
+    pub fn failed_again(&mut self, error: &str, now: DateTime<Utc>) {
+        self.error = error.to_string();
+        self.attempts += 1;
+        self.last_tried = now;
+    }
+
+    /// when the article is worth trying again. The delay doubles after each attempt
+    pub fn next_try(&self) -> DateTime<Utc> {
+        let doublings = self
+            .attempts
+            .saturating_sub(1)
+            .min(ERROR_RETRY_MAX_DOUBLINGS);
+        self.last_tried + ERROR_RETRY_BASE_DELAY * 2i32.pow(doublings)
+    }
+
+    /// first failed longer than `max_age` ago: given up for good
+    pub fn expired(&self, max_age: TimeDelta, now: DateTime<Utc>) -> bool {
+        now - self.first_tried > max_age
+    }
+}
+
+/// adds an error to a list sorted by article number, or counts one more attempt
+pub(crate) fn merge_error(
+    errors: &mut Vec<ArticleError>,
+    article_number: usize,
+    error: &str,
+    now: DateTime<Utc>,
+) {
+    match errors.binary_search_by_key(&article_number, |e| e.article_number) {
+        Ok(i) => errors[i].failed_again(error, now),
+        Err(i) => errors.insert(i, ArticleError::new(article_number, error, now)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_article_error_backoff() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let mut errors = vec![];
+        merge_error(&mut errors, 5, "unavailable", now);
+        merge_error(&mut errors, 2, "unavailable", now);
+        assert_eq!(
+            errors.iter().map(|e| e.article_number).collect::<Vec<_>>(),
+            vec![2, 5]
+        );
+        assert_eq!(errors[0].next_try(), now + TimeDelta::hours(1));
+
+        merge_error(
+            &mut errors,
+            2,
+            "still unavailable",
+            now + TimeDelta::hours(1),
+        );
+        merge_error(
+            &mut errors,
+            2,
+            "still unavailable",
+            now + TimeDelta::hours(3),
+        );
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].attempts, 3);
+        assert_eq!(errors[0].error, "still unavailable");
+        assert_eq!(errors[0].next_try(), now + TimeDelta::hours(7));
+
+        errors[0].attempts = 100;
+        assert_eq!(
+            errors[0].next_try(),
+            now + TimeDelta::hours(3) + TimeDelta::hours(256)
+        );
+        assert!(!errors[0].expired(TimeDelta::days(30), now + TimeDelta::days(30)));
+        assert!(errors[0].expired(TimeDelta::days(30), now + TimeDelta::days(31)));
+    }
+}
@@ -0,0 +1,669 @@
+use super::{ArticleError, ReadStatus, Storage, merge_error};
+use crate::compression::{self, find_article_file};
+use crate::config::{Compression, DuplicateMode};
+use crate::file_utils;
+use crate::message_index::MessageIndex;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeSet, HashMap, hash_map::Entry},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The default layout:
+///
+/// ```text
+/// {base_output_path}/{group}/{article number}.eml[.zst|.gz]
+/// {base_output_path}/{group}/__last_article_number
+/// {base_output_path}/{group}/__errors
+/// ```
+pub struct FileStorage {
+    base_output_path: String,
+    message_index: MessageIndex,
+    errors: ErrorFiles,
+    // damaged articles not fetched again yet, per group, loaded from disk on first use
+    damaged: Mutex<HashMap<String, BTreeSet<usize>>>,
+}
+
+impl FileStorage {
+    pub fn open(
+        base_output_path: &str,
+        duplicate_mode: DuplicateMode,
+        compression: Compression,
+    ) -> io::Result<FileStorage> {
+        file_utils::check_or_create_folder(base_output_path.to_string())?;
+        Ok(FileStorage {
+            base_output_path: base_output_path.to_string(),
+            message_index: MessageIndex::open(base_output_path, duplicate_mode, compression)?,
+            errors: ErrorFiles::new(base_output_path),
+            damaged: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// removes `.tmp` files left by interrupted writes, and moves the newest article
+    /// to `{name}.damaged` if it is incomplete. Articles are written atomically, only
+    /// the last one written by an older version could be. Returns the numbers of the
+    /// `.damaged` articles, including the ones set aside by an earlier run
+    fn scan_damaged_articles(&self, group: &str) -> io::Result<BTreeSet<usize>> {
+        let mut damaged = BTreeSet::new();
+        let group_path = Path::new(&self.base_output_path).join(group);
+        let entries = match fs::read_dir(&group_path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(damaged),
+            Err(e) => return Err(e),
+        };
+
+        let mut newest: Option<(usize, PathBuf)> = None;
+        for entry in entries {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.ends_with(".tmp") {
+                log::warn!("removing {}, left by an interrupted write", path.display());
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if let Some(name) = name.strip_suffix(DAMAGED_SUFFIX) {
+                if let Some((article_number, _)) = compression::parse_article_file_name(name) {
+                    damaged.insert(article_number);
+                }
+                continue;
+            }
+            let Some((article_number, _)) = compression::parse_article_file_name(name) else {
+                continue;
+            };
+            if newest
+                .as_ref()
+                .is_none_or(|(newest, _)| article_number > *newest)
+            {
+                newest = Some((article_number, path));
+            }
+        }
+
+        // an older article is not checked when the newest one is already set aside
+        if let Some((article_number, path)) = newest
+            && damaged.last().is_none_or(|last| article_number > *last)
+            && !compression::article_file_is_complete(&path)
+        {
+            let mut damaged_path = path.clone().into_os_string();
+            damaged_path.push(DAMAGED_SUFFIX);
+            log::warn!(
+                "{} is incomplete, moving it to {}",
+                path.display(),
+                damaged_path.display()
+            );
+            fs::rename(&path, &damaged_path)?;
+            damaged.insert(article_number);
+        }
+        Ok(damaged)
+    }
+
+    /// the article is stored again, or recorded as an error: it is not damaged anymore
+    fn forget_damaged(&self, group: &str, article_number: usize) -> io::Result<()> {
+        let mut damaged = self.damaged.lock().unwrap();
+        let Some(numbers) = damaged.get_mut(group) else {
+            return Ok(());
+        };
+        if !numbers.remove(&article_number) {
+            return Ok(());
+        }
+        let group_path = Path::new(&self.base_output_path).join(group);
+        for compression in compression::ALL {
+            let mut name = compression::article_file_name(article_number, compression);
+            name.push_str(DAMAGED_SUFFIX);
+            match fs::remove_file(group_path.join(name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// added to the name of an incomplete article, until it is fetched again
+const DAMAGED_SUFFIX: &str = ".damaged";
+
+fn read_status_path(base_output_path: &str, group: &str) -> String {
+    format!("{}/{}/__last_article_number", base_output_path, group)
+}
+
+/// reads `{group}/__last_article_number`, also accepting the legacy plain number format
+///
+/// A group without the file was never read. Reading never writes the file, a legacy
+/// one is rewritten with the next read status.
+pub(super) fn read_status_file(base_output_path: &str, group: &str) -> io::Result<ReadStatus> {
+    let status_path = read_status_path(base_output_path, group);
+    match file_utils::read_yaml::<ReadStatus>(status_path.as_str()) {
+        Ok(r) => Ok(r),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ReadStatus::default()),
+        Err(e) => {
+            log::warn!("Error reading status of {group}: {e}");
+            // attempted to read a number from the file, or fallback to 0
+            Ok(ReadStatus {
+                last_email: file_utils::try_read_number(Path::new(&status_path)).unwrap_or(0),
+            })
+        }
+    }
+}
+
+pub(super) fn write_read_status_file(
+    base_output_path: &str,
+    group: &str,
+    read_status: &ReadStatus,
+) -> io::Result<()> {
+    file_utils::write_yaml(
+        read_status_path(base_output_path, group).as_str(),
+        read_status,
+    )
+}
+
+fn error_file_path(base_output_path: &str, group: &str) -> String {
+    format!("{}/{}/__errors", base_output_path, group)
+}
+
+// entries of `__errors` kept beyond twice the errors before it is rewritten
+const ERROR_FILE_SLACK: usize = 64;
+
+/// The `{group}/__errors` files, loaded on first use
+///
+/// A file is a YAML list of `ArticleError`, and each change appends the new state of
+/// one article: its last entry wins, and an entry without attempts means the article
+/// was stored since. The file is rewritten without the older entries once they are
+/// most of it. Reading a file never writes it.
+pub(super) struct ErrorFiles {
+    base_output_path: String,
+    groups: Mutex<HashMap<String, ErrorFile>>,
+}
+
+struct ErrorFile {
+    errors: Vec<ArticleError>,
+    // entries in the file, older states included
+    entries: usize,
+    // in the legacy format, or with an entry cut by a crash
+    needs_rewrite: bool,
+}
+
+impl ErrorFiles {
+    pub(super) fn new(base_output_path: &str) -> ErrorFiles {
+        ErrorFiles {
+            base_output_path: base_output_path.to_string(),
+            groups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(super) fn list(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        let mut groups = self.groups.lock().unwrap();
+        Ok(self.load(&mut groups, group)?.errors.clone())
+    }
+
+    /// adds the error, or counts one more attempt
+    pub(super) fn record(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let file = self.load(&mut groups, group)?;
+        merge_error(&mut file.errors, article_number, error, Utc::now());
+        let entry = file
+            .errors
+            .iter()
+            .find(|e| e.article_number == article_number)
+            .cloned()
+            .expect("the error was just merged");
+        self.append(group, file, &entry)
+    }
+
+    pub(super) fn remove(&self, group: &str, article_number: usize) -> io::Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let file = self.load(&mut groups, group)?;
+        let Some(i) = file
+            .errors
+            .iter()
+            .position(|e| e.article_number == article_number)
+        else {
+            return Ok(());
+        };
+        let mut entry = file.errors.remove(i);
+        entry.attempts = 0;
+        self.append(group, file, &entry)
+    }
+
+    fn load<'a>(
+        &self,
+        groups: &'a mut HashMap<String, ErrorFile>,
+        group: &str,
+    ) -> io::Result<&'a mut ErrorFile> {
+        Ok(match groups.entry(group.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(read_error_file(&self.base_output_path, group)?),
+        })
+    }
+
+    fn append(&self, group: &str, file: &mut ErrorFile, entry: &ArticleError) -> io::Result<()> {
+        let path = error_file_path(&self.base_output_path, group);
+        file.entries += 1;
+        if file.needs_rewrite || file.entries > 2 * file.errors.len() + ERROR_FILE_SLACK {
+            file_utils::write_yaml(&path, &file.errors)?;
+            file.entries = file.errors.len();
+            file.needs_rewrite = false;
+            return Ok(());
+        }
+        let entry = serde_yaml::to_string(&[entry])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        file_utils::append_line_to_file(Path::new(&path), entry.trim_end())
+    }
+}
+
+/// reads `{group}/__errors`, also accepting the legacy format (one
+/// `{article_number},{error}` line per failed attempt)
+fn read_error_file(base_output_path: &str, group: &str) -> io::Result<ErrorFile> {
+    let error_path = error_file_path(base_output_path, group);
+    let content = match fs::read_to_string(&error_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(ErrorFile {
+                errors: vec![],
+                entries: 0,
+                needs_rewrite: false,
+            });
+        }
+        Err(e) => return Err(e),
+    };
+
+    if is_legacy_error_file(&content) {
+        // the legacy lines have no date, the last change of the file is the best guess
+        let recorded = fs::metadata(&error_path)?
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        let mut errors = vec![];
+        for line in content.lines() {
+            if let Some((number, error)) = line.split_once(',')
+                && let Ok(article_number) = number.trim().parse::<usize>()
+            {
+                merge_error(&mut errors, article_number, error, recorded);
+            }
+        }
+        log::info!("{error_path} is in the legacy format, it is converted at the next change");
+        return Ok(ErrorFile {
+            entries: errors.len(),
+            errors,
+            needs_rewrite: true,
+        });
+    }
+
+    let (entries, needs_rewrite) = match serde_yaml::from_str::<Option<Vec<ArticleError>>>(&content)
+    {
+        Ok(entries) => (entries.unwrap_or_default(), false),
+        // an entry cut by a crash while it was appended, the ones before are kept
+        Err(e) => match content
+            .rfind("\n- ")
+            .and_then(|end| serde_yaml::from_str::<Vec<ArticleError>>(&content[..end + 1]).ok())
+        {
+            Some(entries) => {
+                log::warn!("ignoring the last entry of {error_path}, it is incomplete: {e}");
+                (entries, true)
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{error_path} is not a valid errors file: {e}"),
+                ));
+            }
+        },
+    };
+
+    let mut errors: Vec<ArticleError> = vec![];
+    for entry in entries.iter() {
+        match errors.binary_search_by_key(&entry.article_number, |e| e.article_number) {
+            Ok(i) if entry.attempts == 0 => {
+                errors.remove(i);
+            }
+            Ok(i) => errors[i] = entry.clone(),
+            Err(_) if entry.attempts == 0 => {}
+            Err(i) => errors.insert(i, entry.clone()),
+        }
+    }
+    Ok(ErrorFile {
+        errors,
+        entries: entries.len(),
+        needs_rewrite,
+    })
+}
+
+/// every line is `{article_number},{error}`
+fn is_legacy_error_file(content: &str) -> bool {
+    let mut lines = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    lines.peek().is_some()
+        && lines.all(|line| {
+            line.split_once(',')
+                .is_some_and(|(number, _)| number.trim().parse::<usize>().is_ok())
+        })
+}
+
+impl Storage for FileStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        self.message_index
+            .write_article(group, article_number, raw_article)?;
+        self.forget_damaged(group, article_number)
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        let group_path = Path::new(&self.base_output_path).join(group);
+        if let Some(path) = find_article_file(&group_path, article_number) {
+            return Ok(Some(file_utils::read_lines_file(&path)?));
+        }
+
+        // duplicates stored by reference point to the stored copy
+        let manifest = match fs::read_to_string(group_path.join("__duplicates")) {
+            Ok(manifest) => manifest,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let stored_copy = manifest.lines().find_map(|line| {
+            let mut fields = line.split('\t');
+            let number = fields.next()?.parse::<usize>().ok()?;
+            (number == article_number).then(|| fields.nth(1))?
+        });
+        // the stored copy may have been recompressed since
+        match stored_copy.and_then(|stored_copy| {
+            compression::resolve_article_path(&Path::new(&self.base_output_path).join(stored_copy))
+        }) {
+            Some(path) => Ok(Some(file_utils::read_lines_file(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        read_status_file(&self.base_output_path, group)
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        write_read_status_file(&self.base_output_path, group, read_status)
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        self.errors.record(group, article_number, error)?;
+        // retried from the errors file from now on
+        self.forget_damaged(group, article_number)
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        self.errors.list(group)
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.errors.remove(group, article_number)
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let group_path = Path::new(&self.base_output_path).join(group);
+        let mut articles: Vec<usize> = match fs::read_dir(&group_path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    compression::parse_article_file_name(entry.file_name().to_str()?)
+                        .map(|(number, _)| number)
+                })
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+
+        // duplicates stored by reference count as stored
+        if let Ok(manifest) = fs::read_to_string(group_path.join("__duplicates")) {
+            articles.extend(
+                manifest
+                    .lines()
+                    .filter_map(|line| line.split('\t').next()?.parse::<usize>().ok()),
+            );
+        }
+
+        articles.sort_unstable();
+        articles.dedup();
+        Ok(articles)
+    }
+
+    /// the `.damaged` articles of the group. The folder is only scanned the first time
+    fn damaged_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let mut damaged = self.damaged.lock().unwrap();
+        if !damaged.contains_key(group) {
+            damaged.insert(group.to_string(), self.scan_damaged_articles(group)?);
+        }
+        Ok(damaged[group].iter().copied().collect())
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        file_utils::list_folders(&self.base_output_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_storage_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(
+            dir.path().to_str().unwrap(),
+            DuplicateMode::Copy,
+            Compression::None,
+        )
+        .unwrap();
+
+        assert_eq!(storage.get_read_status("g").unwrap().last_email, 0);
+        storage
+            .put_article("g", 2, vec!["Message-ID: <a@b>\n".to_string()])
+            .unwrap();
+        storage
+            .put_article("g", 10, vec!["Message-ID: <c@d>\n".to_string()])
+            .unwrap();
+        storage
+            .put_read_status("g", &ReadStatus { last_email: 10 })
+            .unwrap();
+        storage.record_error("g", 3, "article unavailable").unwrap();
+
+        assert!(dir.path().join("g/2.eml").is_file());
+        assert_eq!(storage.list_articles("g").unwrap(), vec![2, 10]);
+        assert_eq!(storage.get_read_status("g").unwrap().last_email, 10);
+        storage.record_error("g", 3, "article unavailable").unwrap();
+        let errors = storage.list_errors("g").unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].article_number, errors[0].attempts), (3, 2));
+        storage.remove_error("g", 3).unwrap();
+        assert!(storage.list_errors("g").unwrap().is_empty());
+        assert!(storage.list_articles("unknown").unwrap().is_empty());
+        assert!(storage.list_errors("unknown").unwrap().is_empty());
+        assert_eq!(
+            storage.get_article("g", 2).unwrap(),
+            Some(vec!["Message-ID: <a@b>\n".to_string()])
+        );
+        assert_eq!(storage.get_article("g", 3).unwrap(), None);
+    }
+
+    #[test]
+    fn test_damaged_articles() {
+        let dir = tempfile::tempdir().unwrap();
+        let group_path = dir.path().join("g");
+        fs::create_dir_all(&group_path).unwrap();
+        // only the newest article is checked
+        fs::write(group_path.join("1.eml"), "Subject: x\n\nbo").unwrap();
+        fs::write(group_path.join("2.eml"), "Subject: x\n\nbo").unwrap();
+        fs::write(group_path.join("3.eml.tmp"), "Subject").unwrap();
+        let open = || {
+            FileStorage::open(
+                dir.path().to_str().unwrap(),
+                DuplicateMode::Copy,
+                Compression::None,
+            )
+            .unwrap()
+        };
+
+        let storage = open();
+        assert_eq!(storage.damaged_articles("g").unwrap(), vec![2]);
+        assert!(group_path.join("2.eml.damaged").is_file());
+        assert!(!group_path.join("3.eml.tmp").exists());
+        assert_eq!(storage.list_articles("g").unwrap(), vec![1]);
+        assert!(group_path.join("1.eml").is_file());
+        // the folder is scanned once, and the article stays damaged until stored again
+        fs::write(group_path.join("4.eml"), "").unwrap();
+        assert_eq!(storage.damaged_articles("g").unwrap(), vec![2]);
+
+        // set aside by an earlier run
+        fs::remove_file(group_path.join("4.eml")).unwrap();
+        let storage = open();
+        assert_eq!(storage.damaged_articles("g").unwrap(), vec![2]);
+        storage
+            .put_article("g", 2, vec!["Message-ID: <a@b>\r\n".to_string()])
+            .unwrap();
+        assert!(storage.damaged_articles("g").unwrap().is_empty());
+        assert!(!group_path.join("2.eml.damaged").exists());
+        assert_eq!(storage.list_articles("g").unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_legacy_plain_number_status() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("g")).unwrap();
+        fs::write(dir.path().join("g/__last_article_number"), "42\n").unwrap();
+
+        let storage = FileStorage::open(
+            dir.path().to_str().unwrap(),
+            DuplicateMode::Copy,
+            Compression::None,
+        )
+        .unwrap();
+        assert_eq!(storage.get_read_status("g").unwrap().last_email, 42);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("g/__last_article_number")).unwrap(),
+            "42\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_status_is_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(
+            dir.path().to_str().unwrap(),
+            DuplicateMode::Copy,
+            Compression::None,
+        )
+        .unwrap();
+        assert_eq!(storage.get_read_status("g").unwrap(), ReadStatus::default());
+        assert!(!dir.path().join("g").exists());
+    }
+
+    #[test]
+    fn test_legacy_error_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("g")).unwrap();
+        let legacy = "7,article unavailable\n3,article unavailable\n7,article unavailable\n";
+        fs::write(dir.path().join("g/__errors"), legacy).unwrap();
+
+        let storage = FileStorage::open(
+            dir.path().to_str().unwrap(),
+            DuplicateMode::Copy,
+            Compression::None,
+        )
+        .unwrap();
+        let errors = storage.list_errors("g").unwrap();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.article_number, e.attempts))
+                .collect::<Vec<_>>(),
+            vec![(3, 1), (7, 2)]
+        );
+        assert_eq!(errors[0].error, "article unavailable");
+        // reading does not convert it
+        assert_eq!(
+            fs::read_to_string(dir.path().join("g/__errors")).unwrap(),
+            legacy
+        );
+
+        // rewritten in the structured format at the first change
+        storage.remove_error("g", 3).unwrap();
+        assert_eq!(
+            file_utils::read_yaml::<Vec<ArticleError>>(
+                dir.path().join("g/__errors").to_str().unwrap()
+            )
+            .unwrap(),
+            errors[1..]
+        );
+    }
+
+    #[test]
+    fn test_error_file_is_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let path = dir.path().join("g/__errors");
+        let open = || FileStorage::open(base, DuplicateMode::Copy, Compression::None).unwrap();
+        let storage = open();
+
+        storage.record_error("g", 5, "article unavailable").unwrap();
+        storage.record_error("g", 3, "article unavailable").unwrap();
+        storage.record_error("g", 5, "article unavailable").unwrap();
+        storage.remove_error("g", 3).unwrap();
+        let entries = file_utils::read_yaml::<Vec<ArticleError>>(path.to_str().unwrap()).unwrap();
+        assert_eq!(entries.len(), 4);
+        let errors = storage.list_errors("g").unwrap();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| (e.article_number, e.attempts))
+                .collect::<Vec<_>>(),
+            vec![(5, 2)]
+        );
+        assert_eq!(open().list_errors("g").unwrap(), errors);
+
+        // an entry cut by a crash is ignored, and dropped at the next change
+        let mut content = fs::read_to_string(&path).unwrap();
+        content.push_str("- article_number: 9\n  error: art");
+        fs::write(&path, content).unwrap();
+        let storage = open();
+        assert_eq!(storage.list_errors("g").unwrap(), errors);
+        storage.record_error("g", 8, "article unavailable").unwrap();
+        let entries = file_utils::read_yaml::<Vec<ArticleError>>(path.to_str().unwrap()).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // older entries are dropped once they are most of the file
+        for _ in 0..ERROR_FILE_SLACK + 8 {
+            storage.record_error("g", 8, "article unavailable").unwrap();
+        }
+        let entries = file_utils::read_yaml::<Vec<ArticleError>>(path.to_str().unwrap()).unwrap();
+        assert!(entries.len() < ERROR_FILE_SLACK);
+        assert_eq!(
+            open().list_errors("g").unwrap(),
+            storage.list_errors("g").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_error_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("g")).unwrap();
+        fs::write(dir.path().join("g/__errors"), "article_number: [\n").unwrap();
+
+        let storage = FileStorage::open(
+            dir.path().to_str().unwrap(),
+            DuplicateMode::Copy,
+            Compression::None,
+        )
+        .unwrap();
+        let error = storage.list_errors("g").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(storage.record_error("g", 1, "article unavailable").is_err());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("g/__errors")).unwrap(),
+            "article_number: [\n"
+        );
+    }
+}
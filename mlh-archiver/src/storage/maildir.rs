@@ -0,0 +1,282 @@
+use super::files::{ErrorFiles, read_status_file, write_read_status_file};
+use super::{ArticleError, ReadStatus, Storage};
+use crate::config::{Compression, DuplicateMode};
+use crate::file_utils;
+use crate::message_index::{self, ArticleLocation, MessageIndex};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MAILDIR_SUBFOLDERS: [&str; 3] = ["tmp", "new", "cur"];
+
+/// Writes each group as a Maildir, readable by mutt, notmuch and friends:
+///
+/// ```text
+/// {base_output_path}/{group}/{tmp,new,cur}/
+/// {base_output_path}/{group}/new/{seconds}.A{article number}.{hostname}
+/// {base_output_path}/{group}/__last_article_number
+/// {base_output_path}/{group}/__errors
+/// ```
+///
+/// Articles are written in `tmp/` and renamed into `new/`, so readers never see
+/// a partial message. The `A{article number}` part of the name keeps track of the
+/// NNTP article number, even after a mail client moves the message to `cur/`.
+pub struct MaildirStorage {
+    base_output_path: String,
+    hostname: String,
+    message_index: MessageIndex,
+    errors: ErrorFiles,
+    // where each delivered article of a group is, loaded from disk on first use
+    delivered: Mutex<HashMap<String, HashMap<usize, PathBuf>>>,
+}
+
+impl MaildirStorage {
+    pub fn open(base_output_path: &str) -> io::Result<MaildirStorage> {
+        file_utils::check_or_create_folder(base_output_path.to_string())?;
+        Ok(MaildirStorage {
+            base_output_path: base_output_path.to_string(),
+            hostname: maildir_hostname(),
+            // duplicates are always delivered, the index only records locations
+            message_index: MessageIndex::open(
+                base_output_path,
+                DuplicateMode::Copy,
+                Compression::None,
+            )?,
+            delivered: Mutex::new(HashMap::new()),
+            errors: ErrorFiles::new(base_output_path),
+        })
+    }
+
+    fn group_path(&self, group: &str) -> PathBuf {
+        Path::new(&self.base_output_path).join(group)
+    }
+
+    /// current path of a delivered article, in `new/` or `cur/`
+    ///
+    /// The folders are only listed again when a mail client moved or flagged the
+    /// article since it was found.
+    fn find_article(&self, group: &str, article_number: usize) -> io::Result<Option<PathBuf>> {
+        let mut delivered = self.delivered.lock().unwrap();
+        let found = match delivered.get(group) {
+            Some(articles) => articles.get(&article_number).cloned(),
+            None => {
+                let articles = self.scan_articles(group)?;
+                let found = articles.get(&article_number).cloned();
+                delivered.insert(group.to_string(), articles);
+                found
+            }
+        };
+        match found {
+            Some(path) if !path.exists() => {
+                let articles = self.scan_articles(group)?;
+                let found = articles.get(&article_number).cloned();
+                delivered.insert(group.to_string(), articles);
+                Ok(found)
+            }
+            found => Ok(found),
+        }
+    }
+
+    /// reads the paths of the articles of a group from disk
+    fn scan_articles(&self, group: &str) -> io::Result<HashMap<usize, PathBuf>> {
+        let mut articles = HashMap::new();
+        for subfolder in ["new", "cur"] {
+            let folder = self.group_path(group).join(subfolder);
+            let entries = match fs::read_dir(folder) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            articles.extend(entries.filter_map(|entry| {
+                let path = entry.ok()?.path();
+                Some((article_number_from_name(path.file_name()?.to_str()?)?, path))
+            }));
+        }
+        Ok(articles)
+    }
+}
+
+impl Storage for MaildirStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        let group_path = self.group_path(group);
+        for subfolder in MAILDIR_SUBFOLDERS {
+            fs::create_dir_all(group_path.join(subfolder))?;
+        }
+
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let name = format!("{seconds}.A{article_number}.{}", self.hostname);
+        let message_id = message_index::message_id_from_lines(&raw_article);
+
+        // deliver: write in tmp, then rename
+        let tmp_path = group_path
+            .join("tmp")
+            .join(format!("{name}.P{}", std::process::id()));
+        file_utils::write_lines_file(&tmp_path, raw_article)?;
+
+        // an article fetched again replaces the delivered one, keeping its name and flags
+        let destination = self
+            .find_article(group, article_number)?
+            .unwrap_or_else(|| group_path.join("new").join(&name));
+        fs::rename(&tmp_path, &destination)?;
+        self.delivered
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default()
+            .insert(article_number, destination.clone());
+
+        log::debug!("delivered {}", destination.display());
+
+        if let Some(message_id) = message_id {
+            self.message_index.insert(
+                &message_id,
+                ArticleLocation {
+                    group: group.to_string(),
+                    article_number,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        match self.find_article(group, article_number) {
+            Ok(Some(path)) => Ok(Some(file_utils::read_lines_file(&path)?)),
+            Ok(None) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        read_status_file(&self.base_output_path, group)
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        write_read_status_file(&self.base_output_path, group, read_status)
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        self.errors.record(group, article_number, error)
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        self.errors.list(group)
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.errors.remove(group, article_number)
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let mut articles: Vec<usize> = self.scan_articles(group)?.into_keys().collect();
+        articles.sort_unstable();
+        Ok(articles)
+    }
+
+    /// articles are only written in tmp/, so an interrupted delivery is never in new/ or cur/.
+    /// Its leftover in tmp/ is removed
+    fn damaged_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let entries = match fs::read_dir(self.group_path(group).join("tmp")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry
+                .file_name()
+                .to_str()
+                .and_then(article_number_from_name)
+                .is_some()
+            {
+                log::warn!(
+                    "removing {}, left by an interrupted delivery",
+                    entry.path().display()
+                );
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        file_utils::list_folders(&self.base_output_path)
+    }
+}
+
+/// extracts the article number from `{seconds}.A{article number}.{hostname}[:2,flags]`
+fn article_number_from_name(name: &str) -> Option<usize> {
+    name.split('.')
+        .nth(1)?
+        .strip_prefix('A')?
+        .parse::<usize>()
+        .ok()
+}
+
+/// host name part of the Maildir file names. `/` and `:` are encoded, as the spec requires
+fn maildir_hostname() -> String {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "localhost".to_string());
+    hostname.replace('/', "\\057").replace(':', "\\072")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_article_number_from_name() {
+        assert_eq!(article_number_from_name("1700000000.A42.host"), Some(42));
+        assert_eq!(
+            article_number_from_name("1700000000.A42.host:2,S"),
+            Some(42)
+        );
+        assert_eq!(article_number_from_name("1700000000.P1.host"), None);
+        assert_eq!(article_number_from_name("__errors"), None);
+    }
+
+    #[test]
+    fn test_maildir_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = MaildirStorage::open(dir.path().to_str().unwrap()).unwrap();
+        let article = vec!["Message-ID: <a@b>\n".to_string(), "\n".to_string()];
+
+        storage.put_article("g", 5, article.clone()).unwrap();
+        // a mail client reading it moves it to cur/
+        let delivered = storage.find_article("g", 5).unwrap().unwrap();
+        let seen = dir.path().join("g/cur").join(format!(
+            "{}:2,S",
+            delivered.file_name().unwrap().to_str().unwrap()
+        ));
+        fs::rename(&delivered, &seen).unwrap();
+        assert_eq!(storage.find_article("g", 5).unwrap(), Some(seen.clone()));
+        assert_eq!(storage.get_article("g", 5).unwrap(), Some(article.clone()));
+
+        // fetching it again replaces the file in cur/
+        let storage = MaildirStorage::open(dir.path().to_str().unwrap()).unwrap();
+        storage.put_article("g", 5, article.clone()).unwrap();
+        storage.put_article("g", 6, article).unwrap();
+
+        assert_eq!(storage.list_articles("g").unwrap(), vec![5, 6]);
+        assert!(seen.is_file());
+        assert_eq!(fs::read_dir(dir.path().join("g/new")).unwrap().count(), 1);
+        assert_eq!(fs::read_dir(dir.path().join("g/tmp")).unwrap().count(), 0);
+    }
+}
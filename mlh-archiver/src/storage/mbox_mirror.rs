@@ -0,0 +1,147 @@
+use super::{ArticleError, ReadStatus, Storage};
+use crate::mbox;
+use std::{io, path::PathBuf, sync::Mutex};
+
+/// Wraps another storage, and also appends every new article to the mboxrd
+/// file of its month:
+///
+/// ```text
+/// {mbox_path}/{group}/{YYYY-MM}.mbox
+/// ```
+///
+/// The wrapped storage stays the archive of record: failing to update the mbox
+/// is logged, but does not stop the fetch. The mbox files can always be written
+/// again with the export-mbox subcommand.
+///
+/// Articles the wrapped storage already holds (fetched again by a range, a retry or
+/// a backfill) are not appended, so the mbox never gets a message twice.
+pub struct MboxMirror {
+    inner: Box<dyn Storage>,
+    mbox_path: PathBuf,
+    // one append at a time, so messages are never interleaved
+    lock: Mutex<()>,
+}
+
+impl MboxMirror {
+    pub fn new(inner: Box<dyn Storage>, mbox_path: &str) -> MboxMirror {
+        MboxMirror {
+            inner,
+            mbox_path: PathBuf::from(mbox_path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// whether the wrapped storage holds the article already. An unreadable copy
+    /// does not count
+    fn is_stored(&self, group: &str, article_number: usize) -> bool {
+        matches!(self.inner.get_article(group, article_number), Ok(Some(_)))
+    }
+
+    fn append(&self, group: &str, article_number: usize, raw_article: &[String]) {
+        let _guard = self.lock.lock().unwrap();
+        if let Err(e) = mbox::append_to_month_mbox(&self.mbox_path, group, raw_article) {
+            log::error!("could not append {group}/{article_number} to the mbox: {e}");
+        }
+    }
+}
+
+impl Storage for MboxMirror {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        let stored = self.is_stored(group, article_number);
+        self.inner
+            .put_article(group, article_number, raw_article.clone())?;
+        if !stored {
+            self.append(group, article_number, &raw_article);
+        }
+        Ok(())
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        self.inner.get_article(group, article_number)
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        self.inner.get_read_status(group)
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        self.inner.put_read_status(group, read_status)
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        self.inner.record_error(group, article_number, error)
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        self.inner.list_errors(group)
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.inner.remove_error(group, article_number)
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        self.inner.list_articles(group)
+    }
+
+    fn damaged_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        self.inner.damaged_articles(group)
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        self.inner.list_groups()
+    }
+
+    fn put_article_and_read_status(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+        read_status: &ReadStatus,
+    ) -> io::Result<()> {
+        let stored = self.is_stored(group, article_number);
+        self.inner.put_article_and_read_status(
+            group,
+            article_number,
+            raw_article.clone(),
+            read_status,
+        )?;
+        if !stored {
+            self.append(group, article_number, &raw_article);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::fs;
+
+    #[test]
+    fn test_article_fetched_again_is_appended_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mirror = MboxMirror::new(Box::new(MemoryStorage::new()), dir.path().to_str().unwrap());
+        let article = vec![
+            "Message-ID: <a@example.org>\r\n".to_string(),
+            "Date: Mon, 1 Jan 2024 00:00:00 +0000\r\n".to_string(),
+            "\r\n".to_string(),
+            "body\r\n".to_string(),
+        ];
+
+        mirror.put_article("g", 1, article.clone()).unwrap();
+        mirror
+            .put_article_and_read_status("g", 1, article.clone(), &ReadStatus::default())
+            .unwrap();
+        mirror.put_article("g", 2, article.clone()).unwrap();
+
+        let mbox = fs::read_to_string(mbox::month_mbox_path(dir.path(), "g", &article)).unwrap();
+        assert_eq!(mbox.matches("\nbody").count(), 2);
+    }
+}
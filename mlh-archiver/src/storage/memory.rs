@@ -0,0 +1,128 @@
+use super::{ArticleError, ReadStatus, Storage, merge_error};
+use chrono::Utc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::Mutex,
+};
+
+#[derive(Default)]
+struct Group {
+    articles: BTreeMap<usize, Vec<String>>,
+    read_status: ReadStatus,
+    errors: Vec<ArticleError>,
+}
+
+/// Keeps everything in memory. Meant for tests, nothing is persisted
+#[derive(Default)]
+pub struct MemoryStorage {
+    groups: Mutex<HashMap<String, Group>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// raw lines of a stored article
+    pub fn article(&self, group: &str, article_number: usize) -> Option<Vec<String>> {
+        self.groups
+            .lock()
+            .unwrap()
+            .get(group)?
+            .articles
+            .get(&article_number)
+            .cloned()
+    }
+
+    /// records an error as is, to set its attempts and times
+    pub fn put_error(&self, group: &str, error: ArticleError) {
+        let mut groups = self.groups.lock().unwrap();
+        let errors = &mut groups.entry(group.to_string()).or_default().errors;
+        errors.retain(|e| e.article_number != error.article_number);
+        errors.push(error);
+        errors.sort_by_key(|e| e.article_number);
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        self.groups
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default()
+            .articles
+            .insert(article_number, raw_article);
+        Ok(())
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        Ok(self.article(group, article_number))
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        Ok(self
+            .groups
+            .lock()
+            .unwrap()
+            .get(group)
+            .map(|g| g.read_status)
+            .unwrap_or_default())
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        self.groups
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default()
+            .read_status = *read_status;
+        Ok(())
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        let mut groups = self.groups.lock().unwrap();
+        let errors = &mut groups.entry(group.to_string()).or_default().errors;
+        merge_error(errors, article_number, error, Utc::now());
+        Ok(())
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        Ok(self
+            .groups
+            .lock()
+            .unwrap()
+            .get(group)
+            .map(|g| g.errors.clone())
+            .unwrap_or_default())
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        if let Some(g) = self.groups.lock().unwrap().get_mut(group) {
+            g.errors.retain(|e| e.article_number != article_number);
+        }
+        Ok(())
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        Ok(self
+            .groups
+            .lock()
+            .unwrap()
+            .get(group)
+            .map(|g| g.articles.keys().copied().collect())
+            .unwrap_or_default())
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        let mut groups: Vec<String> = self.groups.lock().unwrap().keys().cloned().collect();
+        groups.sort();
+        Ok(groups)
+    }
+}
@@ -0,0 +1,172 @@
+use super::{ArticleError, ReadStatus, Storage};
+use crate::config::GroupPattern;
+use std::{io, sync::Arc};
+
+/// Sends each group to its own storage, for groups configured with another output
+/// format than the global one. The other groups go to the default storage.
+///
+/// The storages of the other formats live in subfolders of the output folder, which
+/// are not listed as groups of the default storage.
+pub struct PerGroupStorage {
+    default: Box<dyn Storage>,
+    // the first matching pattern wins. None is the default storage
+    routes: Vec<(GroupPattern, Option<Arc<dyn Storage>>)>,
+    // folders of the output folder holding the other storages
+    subfolders: Vec<String>,
+}
+
+impl PerGroupStorage {
+    pub fn new(
+        default: Box<dyn Storage>,
+        routes: Vec<(GroupPattern, Option<Arc<dyn Storage>>)>,
+        subfolders: Vec<String>,
+    ) -> PerGroupStorage {
+        PerGroupStorage {
+            default,
+            routes,
+            subfolders,
+        }
+    }
+
+    /// the storage of a group, if it is not the default one
+    fn route(&self, group: &str) -> Option<&Arc<dyn Storage>> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| pattern.matches(group))
+            .and_then(|(_, storage)| storage.as_ref())
+    }
+
+    fn storage(&self, group: &str) -> &dyn Storage {
+        match self.route(group) {
+            Some(storage) => storage.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+impl Storage for PerGroupStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        self.storage(group)
+            .put_article(group, article_number, raw_article)
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        self.storage(group).get_article(group, article_number)
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        self.storage(group).get_read_status(group)
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        self.storage(group).put_read_status(group, read_status)
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        self.storage(group)
+            .record_error(group, article_number, error)
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        self.storage(group).list_errors(group)
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.storage(group).remove_error(group, article_number)
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        self.storage(group).list_articles(group)
+    }
+
+    fn damaged_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        self.storage(group).damaged_articles(group)
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        let mut groups: Vec<String> = self
+            .default
+            .list_groups()?
+            .into_iter()
+            .filter(|group| !self.subfolders.contains(group) && self.route(group).is_none())
+            .collect();
+
+        let mut others: Vec<&Arc<dyn Storage>> = vec![];
+        for storage in self
+            .routes
+            .iter()
+            .filter_map(|(_, storage)| storage.as_ref())
+        {
+            if !others.iter().any(|other| Arc::ptr_eq(other, storage)) {
+                others.push(storage);
+            }
+        }
+        for storage in others {
+            for group in storage.list_groups()? {
+                if self
+                    .route(&group)
+                    .is_some_and(|route| Arc::ptr_eq(route, storage))
+                {
+                    groups.push(group);
+                }
+            }
+        }
+        groups.sort();
+        Ok(groups)
+    }
+
+    fn put_article_and_read_status(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+        read_status: &ReadStatus,
+    ) -> io::Result<()> {
+        self.storage(group).put_article_and_read_status(
+            group,
+            article_number,
+            raw_article,
+            read_status,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_groups_are_routed() {
+        let default = MemoryStorage::new();
+        default.put_article("a", 1, vec![]).unwrap();
+        // the folder of the other storage, as a file storage would list it
+        default.put_article("maildir", 1, vec![]).unwrap();
+        let other = Arc::new(MemoryStorage::new());
+        let pattern = |pattern: &str| GroupPattern::parse(pattern).unwrap();
+        let storage = PerGroupStorage::new(
+            Box::new(default),
+            vec![
+                (pattern("b.default"), None),
+                (pattern("b*"), Some(other.clone() as Arc<dyn Storage>)),
+                (pattern("c"), Some(other.clone() as Arc<dyn Storage>)),
+            ],
+            vec!["maildir".to_string()],
+        );
+
+        storage
+            .put_article("b", 2, vec!["x\n".to_string()])
+            .unwrap();
+        storage.put_article("b.default", 3, vec![]).unwrap();
+        assert_eq!(other.list_articles("b").unwrap(), vec![2]);
+        assert_eq!(storage.get_article("b", 2).unwrap().unwrap(), vec!["x\n"]);
+        assert_eq!(storage.list_articles("a").unwrap(), vec![1]);
+        assert!(other.list_articles("b.default").unwrap().is_empty());
+        assert_eq!(storage.list_groups().unwrap(), vec!["a", "b", "b.default"]);
+    }
+}
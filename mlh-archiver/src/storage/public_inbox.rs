@@ -0,0 +1,423 @@
+mod git;
+
+use super::files::{ErrorFiles, read_status_file, write_read_status_file};
+use super::{ArticleError, ReadStatus, Storage};
+use crate::config::{Compression, DuplicateMode};
+use crate::file_utils;
+use crate::mbox::article_date;
+use crate::message_index::{self, ArticleLocation, MessageIndex};
+use git::BareRepo;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// public-inbox starts a new epoch when the current one reaches about 1GB
+const EPOCH_SIZE_LIMIT: u64 = 1024 * 1024 * 1024;
+/// `{article number}\t{epoch}\t{blob id}` for each stored article
+const ARTICLE_MAP_FILE_NAME: &str = "__public_inbox_map";
+const COMMITTER: &str = "mlh-archiver <mlh-archiver@localhost>";
+
+/// Writes each group as a public-inbox v2 inbox, that public-inbox tools can index and serve:
+///
+/// ```text
+/// {base_output_path}/{group}/git/{epoch}.git   one bare repository per epoch
+/// {base_output_path}/{group}/all.git           alternates to all epochs
+/// {base_output_path}/{group}/__public_inbox_map
+/// {base_output_path}/{group}/__last_article_number
+/// {base_output_path}/{group}/__errors
+/// ```
+///
+/// Each article is one commit, whose tree only holds the message in the `m` blob.
+/// The commit is authored from the From and Date headers, with the Subject as message.
+/// NNTP article numbers are not part of the inbox, so `__public_inbox_map` keeps
+/// track of the blob of each article.
+pub struct PublicInboxStorage {
+    base_output_path: String,
+    message_index: MessageIndex,
+    errors: ErrorFiles,
+    epoch_size_limit: u64,
+    inboxes: Mutex<HashMap<String, Inbox>>,
+}
+
+/// state of a group's inbox, loaded from disk on first use
+struct Inbox {
+    articles: BTreeMap<usize, (usize, String)>,
+    epoch: usize,
+    epoch_size: u64,
+}
+
+impl PublicInboxStorage {
+    pub fn open(base_output_path: &str) -> io::Result<PublicInboxStorage> {
+        file_utils::check_or_create_folder(base_output_path.to_string())?;
+        Ok(PublicInboxStorage {
+            base_output_path: base_output_path.to_string(),
+            // each inbox has its own copy, the index only records locations
+            message_index: MessageIndex::open(
+                base_output_path,
+                DuplicateMode::Copy,
+                Compression::None,
+            )?,
+            epoch_size_limit: EPOCH_SIZE_LIMIT,
+            inboxes: Mutex::new(HashMap::new()),
+            errors: ErrorFiles::new(base_output_path),
+        })
+    }
+
+    fn group_path(&self, group: &str) -> PathBuf {
+        Path::new(&self.base_output_path).join(group)
+    }
+
+    fn epoch_path(&self, group: &str, epoch: usize) -> PathBuf {
+        self.group_path(group).join(format!("git/{epoch}.git"))
+    }
+
+    fn epoch_repo(&self, group: &str, epoch: usize) -> io::Result<BareRepo> {
+        BareRepo::init(&self.epoch_path(group, epoch))
+    }
+
+    fn load_inbox(&self, group: &str) -> io::Result<Inbox> {
+        let mut articles = BTreeMap::new();
+        match fs::read_to_string(self.group_path(group).join(ARTICLE_MAP_FILE_NAME)) {
+            Ok(map) => {
+                for line in map.lines() {
+                    match parse_map_line(line) {
+                        Some((article_number, epoch, blob)) => {
+                            articles.insert(article_number, (epoch, blob));
+                        }
+                        None => log::warn!("ignoring invalid {ARTICLE_MAP_FILE_NAME} line: {line}"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        // the last epoch is the one still written
+        let epoch = fs::read_dir(self.group_path(group).join("git"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| {
+                        entry
+                            .ok()?
+                            .file_name()
+                            .to_str()?
+                            .strip_suffix(".git")?
+                            .parse::<usize>()
+                            .ok()
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        let epoch_size = match self.epoch_path(group, epoch).is_dir() {
+            true => self.epoch_repo(group, epoch)?.size()?,
+            false => 0,
+        };
+
+        Ok(Inbox {
+            articles,
+            epoch,
+            epoch_size,
+        })
+    }
+
+    /// points all.git to the objects of every epoch, as public-inbox does
+    fn link_epochs(&self, group: &str, last_epoch: usize) -> io::Result<()> {
+        let all = BareRepo::init(&self.group_path(group).join("all.git"))?;
+        let alternates: String = (0..=last_epoch)
+            .map(|epoch| format!("../../git/{epoch}.git/objects\n"))
+            .collect();
+        file_utils::write_atomic(
+            &all.objects_path().join("info/alternates"),
+            alternates.as_bytes(),
+        )
+    }
+}
+
+impl Storage for PublicInboxStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        if !inboxes.contains_key(group) {
+            inboxes.insert(group.to_string(), self.load_inbox(group)?);
+        }
+        let inbox = inboxes.get_mut(group).unwrap();
+
+        // commits are never rewritten, an article fetched again is kept as it was
+        if inbox.articles.contains_key(&article_number) {
+            log::debug!("{group}/{article_number} is already in the inbox");
+            return Ok(());
+        }
+
+        let message = message_bytes(&raw_article);
+        if inbox.epoch_size > 0 && inbox.epoch_size + message.len() as u64 > self.epoch_size_limit {
+            inbox.epoch += 1;
+            inbox.epoch_size = 0;
+            log::info!("{group}: starting epoch {}", inbox.epoch);
+        }
+        let new_epoch = !self.epoch_path(group, inbox.epoch).is_dir();
+        let repo = self.epoch_repo(group, inbox.epoch)?;
+        if new_epoch {
+            self.link_epochs(group, inbox.epoch)?;
+        }
+
+        let blob = repo.write_object("blob", &message)?;
+        let mut tree_content = b"100644 m\0".to_vec();
+        tree_content.extend(git::id_bytes(&blob));
+        let tree = repo.write_object("tree", &tree_content)?;
+
+        let mut commit_content = format!("tree {tree}\n");
+        if let Some(parent) = repo.head()? {
+            commit_content.push_str(&format!("parent {parent}\n"));
+        }
+        let (author, timestamp) = author_ident(&raw_article);
+        commit_content.push_str(&format!(
+            "author {author} {timestamp}\ncommitter {COMMITTER} {timestamp}\n\n{}\n",
+            commit_subject(&raw_article)
+        ));
+        let commit = repo.write_object("commit", commit_content.as_bytes())?;
+        repo.set_head(&commit)?;
+
+        file_utils::append_line_to_file(
+            &self.group_path(group).join(ARTICLE_MAP_FILE_NAME),
+            &format!("{article_number}\t{}\t{blob}", inbox.epoch),
+        )?;
+        inbox.epoch_size += message.len() as u64;
+        inbox.articles.insert(article_number, (inbox.epoch, blob));
+        drop(inboxes);
+
+        if let Some(message_id) = message_index::message_id_from_lines(&raw_article) {
+            self.message_index.insert(
+                &message_id,
+                ArticleLocation {
+                    group: group.to_string(),
+                    article_number,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        if !inboxes.contains_key(group) {
+            inboxes.insert(group.to_string(), self.load_inbox(group)?);
+        }
+        let Some((epoch, blob)) = inboxes[group].articles.get(&article_number).cloned() else {
+            return Ok(None);
+        };
+        drop(inboxes);
+
+        let message = self.epoch_repo(group, epoch)?.read_blob(&blob)?;
+        Ok(Some(
+            String::from_utf8_lossy(&message)
+                .split_inclusive('\n')
+                .map(str::to_string)
+                .collect(),
+        ))
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        read_status_file(&self.base_output_path, group)
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        write_read_status_file(&self.base_output_path, group, read_status)
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        self.errors.record(group, article_number, error)
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        self.errors.list(group)
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.errors.remove(group, article_number)
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let mut inboxes = self.inboxes.lock().unwrap();
+        if !inboxes.contains_key(group) {
+            inboxes.insert(group.to_string(), self.load_inbox(group)?);
+        }
+        Ok(inboxes[group].articles.keys().copied().collect())
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        file_utils::list_folders(&self.base_output_path)
+    }
+}
+
+/// the message as public-inbox stores it, with LF line endings
+fn message_bytes(raw_article: &[String]) -> Vec<u8> {
+    let mut message = vec![];
+    for line in raw_article {
+        let (content, ending) = match line.strip_suffix('\n') {
+            Some(content) => (content.strip_suffix('\r').unwrap_or(content), "\n"),
+            None => (line.as_str(), ""),
+        };
+        message.extend_from_slice(content.as_bytes());
+        message.extend_from_slice(ending.as_bytes());
+    }
+    message
+}
+
+/// `Name <email>` from the From header, and `{unix time} {offset}` from the Date header
+fn author_ident(raw_article: &[String]) -> (String, String) {
+    let from = message_index::header_from_lines(raw_article, "from").unwrap_or_default();
+    let (name, email) = match (from.rfind('<'), from.rfind('>')) {
+        // Name <email>
+        (Some(start), Some(end)) if start < end => (
+            from[..start].trim().trim_matches('"').to_string(),
+            from[start + 1..end].to_string(),
+        ),
+        // email (Name), or only the email
+        _ => match from.split_once('(') {
+            Some((email, name)) => (name.trim_end_matches(')').to_string(), email.to_string()),
+            None => (String::new(), from.clone()),
+        },
+    };
+    let clean = |value: &str| -> String {
+        value
+            .chars()
+            .filter(|c| !matches!(c, '<' | '>' | '\n' | '\r'))
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+    let email: String = clean(&email).split_whitespace().collect();
+    let mut name = clean(&name);
+    if name.is_empty() {
+        name = email.split('@').next().unwrap_or_default().to_string();
+    }
+
+    let timestamp = match article_date(raw_article) {
+        Some(date) => {
+            let offset_minutes = date.offset().local_minus_utc() / 60;
+            format!(
+                "{} {}{:02}{:02}",
+                date.timestamp().max(0),
+                if offset_minutes < 0 { '-' } else { '+' },
+                offset_minutes.abs() / 60,
+                offset_minutes.abs() % 60
+            )
+        }
+        None => "0 +0000".to_string(),
+    };
+    (format!("{name} <{email}>"), timestamp)
+}
+
+fn commit_subject(raw_article: &[String]) -> String {
+    message_index::header_from_lines(raw_article, "subject")
+        .unwrap_or_else(|| "(no subject)".to_string())
+}
+
+fn parse_map_line(line: &str) -> Option<(usize, usize, String)> {
+    let mut fields = line.split('\t');
+    let article_number = fields.next()?.parse::<usize>().ok()?;
+    let epoch = fields.next()?.parse::<usize>().ok()?;
+    let blob = fields.next()?.trim();
+    (blob.len() == 40).then(|| (article_number, epoch, blob.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn article(number: usize) -> Vec<String> {
+        vec![
+            "From: \"Demo User\" <nobody@example.com>\r\n".to_string(),
+            "Date: Tue, 6 Oct 1998 04:38:40 -0500\r\n".to_string(),
+            format!("Message-ID: <{number}@example.com>\r\n"),
+            format!("Subject: test {number}\r\n"),
+            "\r\n".to_string(),
+            "body\r\n".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_author_ident() {
+        assert_eq!(
+            author_ident(&article(1)),
+            (
+                "Demo User <nobody@example.com>".to_string(),
+                "907666720 -0500".to_string()
+            )
+        );
+        let raw = vec!["From: nobody@example.com (Demo)\n".to_string()];
+        assert_eq!(
+            author_ident(&raw),
+            (
+                "Demo <nobody@example.com>".to_string(),
+                "0 +0000".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_public_inbox_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = PublicInboxStorage::open(dir.path().to_str().unwrap()).unwrap();
+        // a new epoch after each message
+        storage.epoch_size_limit = 1;
+
+        storage.put_article("g", 1, article(1)).unwrap();
+        storage.put_article("g", 2, article(2)).unwrap();
+        storage.put_article("g", 2, article(2)).unwrap();
+        storage.put_article("g", 3, article(3)).unwrap();
+
+        assert_eq!(storage.list_articles("g").unwrap(), vec![1, 2, 3]);
+        assert!(storage.list_articles("unknown").unwrap().is_empty());
+        assert!(!dir.path().join("unknown").exists());
+        let stored = storage.get_article("g", 2).unwrap().unwrap();
+        assert_eq!(stored[3], "Subject: test 2\n");
+        assert!(dir.path().join("g/git/2.git").is_dir());
+
+        // loaded again from disk
+        let storage = PublicInboxStorage::open(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(storage.list_articles("g").unwrap(), vec![1, 2, 3]);
+        storage.put_article("g", 4, article(4)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("g/all.git/objects/info/alternates"))
+                .unwrap()
+                .lines()
+                .count(),
+            3
+        );
+
+        // git may not be installed where the tests run
+        let Ok(fsck) = Command::new("git")
+            .arg("--git-dir")
+            .arg(dir.path().join("g/git/2.git"))
+            .args(["fsck", "--strict"])
+            .output()
+        else {
+            return;
+        };
+        assert!(
+            fsck.status.success(),
+            "{}",
+            String::from_utf8_lossy(&fsck.stderr)
+        );
+        let log = Command::new("git")
+            .arg("--git-dir")
+            .arg(dir.path().join("g/git/2.git"))
+            .args(["log", "--format=%an|%ae|%s"])
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&log.stdout),
+            "Demo User|nobody@example.com|test 4\nDemo User|nobody@example.com|test 3\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::file_utils;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use sha1::{Digest, Sha1};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const BRANCH: &str = "refs/heads/master";
+
+/// A bare git repository, written with loose objects and a single branch.
+///
+/// Only what the public-inbox layout needs is implemented. Objects packed by
+/// `git gc` are read back with the git command line.
+pub struct BareRepo {
+    path: PathBuf,
+}
+
+impl BareRepo {
+    /// opens the repository, creating it if needed
+    pub fn init(path: &Path) -> io::Result<BareRepo> {
+        for folder in ["objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+            fs::create_dir_all(path.join(folder))?;
+        }
+        if !path.join("HEAD").is_file() {
+            fs::write(path.join("HEAD"), format!("ref: {BRANCH}\n"))?;
+            fs::write(
+                path.join("config"),
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = true\n",
+            )?;
+        }
+        Ok(BareRepo {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn objects_path(&self) -> PathBuf {
+        self.path.join("objects")
+    }
+
+    /// writes a loose object, and returns its id (hex SHA-1)
+    pub fn write_object(&self, kind: &str, content: &[u8]) -> io::Result<String> {
+        let mut object = format!("{kind} {}\0", content.len()).into_bytes();
+        object.extend_from_slice(content);
+        let id = hex(&Sha1::digest(&object));
+
+        let object_path = self.objects_path().join(&id[..2]).join(&id[2..]);
+        if object_path.is_file() {
+            return Ok(id);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&object)?;
+        file_utils::write_atomic(&object_path, &encoder.finish()?)?;
+        Ok(id)
+    }
+
+    /// content of a blob
+    pub fn read_blob(&self, id: &str) -> io::Result<Vec<u8>> {
+        if id.len() != 40 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid object id {id}"),
+            ));
+        }
+        let object_path = self.objects_path().join(&id[..2]).join(&id[2..]);
+        let compressed = match fs::read(&object_path) {
+            Ok(compressed) => compressed,
+            // not a loose object anymore, probably packed by git gc
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.cat_file(id),
+            Err(e) => return Err(e),
+        };
+
+        let mut object = vec![];
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut object)?;
+        match object.iter().position(|b| *b == 0) {
+            Some(end) if object.starts_with(b"blob ") => Ok(object.split_off(end + 1)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a blob", object_path.display()),
+            )),
+        }
+    }
+
+    /// commit the branch points to, if any
+    pub fn head(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.path.join(BRANCH)) {
+            Ok(id) => return Ok(Some(id.trim().to_string())),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        // git gc moves refs to packed-refs
+        match fs::read_to_string(self.path.join("packed-refs")) {
+            Ok(packed) => Ok(packed.lines().find_map(|line| {
+                let (id, name) = line.split_once(' ')?;
+                (name == BRANCH).then(|| id.to_string())
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_head(&self, commit: &str) -> io::Result<()> {
+        file_utils::write_atomic(&self.path.join(BRANCH), format!("{commit}\n").as_bytes())
+    }
+
+    /// disk space used by the objects
+    pub fn size(&self) -> io::Result<u64> {
+        fn folder_size(path: &Path) -> io::Result<u64> {
+            let mut size = 0;
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                size += match metadata.is_dir() {
+                    true => folder_size(&entry.path())?,
+                    false => metadata.len(),
+                };
+            }
+            Ok(size)
+        }
+        folder_size(&self.objects_path())
+    }
+
+    fn cat_file(&self, id: &str) -> io::Result<Vec<u8>> {
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(&self.path)
+            .args(["cat-file", "blob", id])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "object {id} not found in {}: {}",
+                    self.path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// binary form of a hex object id, as used in tree entries
+pub fn id_bytes(id: &str) -> Vec<u8> {
+    (0..id.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(id.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_id_matches_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = BareRepo::init(dir.path()).unwrap();
+        // echo 'test content' | git hash-object --stdin
+        let id = repo.write_object("blob", b"test content\n").unwrap();
+        assert_eq!(id, "d670460b4b4aece5915caf5c68d12f560a9fe3e4");
+        assert_eq!(repo.read_blob(&id).unwrap(), b"test content\n");
+        assert_eq!(id_bytes(&id).len(), 20);
+        assert_eq!(hex(&id_bytes(&id)), id);
+    }
+
+    #[test]
+    fn test_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = BareRepo::init(dir.path()).unwrap();
+        assert_eq!(repo.head().unwrap(), None);
+
+        let packed = "# pack-refs with: peeled fully-peeled sorted\n\
+                      1111111111111111111111111111111111111111 refs/heads/master\n";
+        fs::write(dir.path().join("packed-refs"), packed).unwrap();
+        assert_eq!(
+            repo.head().unwrap().as_deref(),
+            Some("1111111111111111111111111111111111111111")
+        );
+
+        repo.set_head("2222222222222222222222222222222222222222")
+            .unwrap();
+        assert_eq!(
+            repo.head().unwrap().as_deref(),
+            Some("2222222222222222222222222222222222222222")
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use super::{ArticleError, ReadStatus, Storage};
+use crate::file_utils;
+use crate::mbox::article_date;
+use crate::message_index::{header_from_lines, message_id_from_lines};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use std::{io, path::Path, sync::Mutex};
+
+pub const DATABASE_FILE_NAME: &str = "archive.sqlite3";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS articles (
+    group_name TEXT NOT NULL,
+    article_number INTEGER NOT NULL,
+    message_id TEXT,
+    from_header TEXT,
+    subject TEXT,
+    date_header TEXT,
+    -- Date header as unix time, when it could be parsed
+    date_unix INTEGER,
+    references_header TEXT,
+    raw BLOB NOT NULL,
+    PRIMARY KEY (group_name, article_number)
+);
+CREATE INDEX IF NOT EXISTS articles_message_id ON articles (message_id);
+CREATE INDEX IF NOT EXISTS articles_date ON articles (date_unix);
+
+CREATE TABLE IF NOT EXISTS read_status (
+    group_name TEXT PRIMARY KEY,
+    last_email INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS errors (
+    group_name TEXT NOT NULL,
+    article_number INTEGER NOT NULL,
+    -- error of the last attempt
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    -- RFC 3339 dates
+    first_tried TEXT NOT NULL,
+    last_tried TEXT NOT NULL,
+    PRIMARY KEY (group_name, article_number)
+);
+";
+
+/// Keeps everything in a single SQLite database, `{base_output_path}/archive.sqlite3`:
+///
+/// - `articles`: one row per article, with the main headers in columns and the raw article
+/// - `read_status`: the last article read of each group
+/// - `errors`: articles the server could not provide
+///
+/// An article and the read status that follows it are written in one transaction,
+/// so the read status can never point past an article that was not stored.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(base_output_path: &str) -> io::Result<SqliteStorage> {
+        file_utils::check_or_create_folder(base_output_path.to_string())?;
+        let connection = Connection::open(Path::new(base_output_path).join(DATABASE_FILE_NAME))
+            .map_err(io::Error::other)?;
+        // readers (queries on the archive) do not block the workers
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(io::Error::other)?;
+        connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// runs `f` in a transaction, committed if it succeeds
+    fn transaction<T>(&self, f: impl FnOnce(&Transaction) -> rusqlite::Result<T>) -> io::Result<T> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        let result = f(&transaction).map_err(io::Error::other)?;
+        transaction.commit().map_err(io::Error::other)?;
+        Ok(result)
+    }
+}
+
+fn insert_article(
+    transaction: &Transaction,
+    group: &str,
+    article_number: usize,
+    raw_article: &[String],
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO articles (group_name, article_number, message_id, from_header,
+            subject, date_header, date_unix, references_header, raw)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            group,
+            article_number as i64,
+            message_id_from_lines(raw_article),
+            header_from_lines(raw_article, "from"),
+            header_from_lines(raw_article, "subject"),
+            header_from_lines(raw_article, "date"),
+            article_date(raw_article).map(|date| date.timestamp()),
+            header_from_lines(raw_article, "references"),
+            raw_article.concat().as_bytes(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_read_status(
+    transaction: &Transaction,
+    group: &str,
+    read_status: &ReadStatus,
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT INTO read_status (group_name, last_email) VALUES (?1, ?2)
+        ON CONFLICT (group_name) DO UPDATE SET last_email = excluded.last_email",
+        params![group, read_status.last_email as i64],
+    )?;
+    Ok(())
+}
+
+fn parse_date(date: &str) -> io::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.to_utc())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl Storage for SqliteStorage {
+    fn put_article(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+    ) -> io::Result<()> {
+        self.transaction(|transaction| {
+            insert_article(transaction, group, article_number, &raw_article)
+        })
+    }
+
+    fn get_article(&self, group: &str, article_number: usize) -> io::Result<Option<Vec<String>>> {
+        let raw: Option<Vec<u8>> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT raw FROM articles WHERE group_name = ?1 AND article_number = ?2",
+                params![group, article_number as i64],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(io::Error::other)?;
+        Ok(raw.map(|raw| {
+            String::from_utf8_lossy(&raw)
+                .split_inclusive('\n')
+                .map(str::to_string)
+                .collect()
+        }))
+    }
+
+    fn get_read_status(&self, group: &str) -> io::Result<ReadStatus> {
+        let last_email: Option<i64> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT last_email FROM read_status WHERE group_name = ?1",
+                params![group],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(io::Error::other)?;
+        Ok(ReadStatus {
+            last_email: last_email.unwrap_or(0) as usize,
+        })
+    }
+
+    fn put_read_status(&self, group: &str, read_status: &ReadStatus) -> io::Result<()> {
+        self.transaction(|transaction| update_read_status(transaction, group, read_status))
+    }
+
+    fn record_error(&self, group: &str, article_number: usize, error: &str) -> io::Result<()> {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        self.transaction(|transaction| {
+            transaction.execute(
+                "INSERT INTO errors (group_name, article_number, error, attempts, first_tried, last_tried)
+                VALUES (?1, ?2, ?3, 1, ?4, ?4)
+                ON CONFLICT (group_name, article_number) DO UPDATE SET
+                    error = excluded.error, attempts = attempts + 1, last_tried = excluded.last_tried",
+                params![group, article_number as i64, error, now],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn list_errors(&self, group: &str) -> io::Result<Vec<ArticleError>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT article_number, error, attempts, first_tried, last_tried FROM errors
+                WHERE group_name = ?1 ORDER BY article_number",
+            )
+            .map_err(io::Error::other)?;
+        let rows = statement
+            .query_map(params![group], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .map_err(io::Error::other)?;
+        rows.map(|row| {
+            let (article_number, error, attempts, first_tried, last_tried) =
+                row.map_err(io::Error::other)?;
+            Ok(ArticleError {
+                article_number: article_number as usize,
+                error,
+                attempts,
+                first_tried: parse_date(&first_tried)?,
+                last_tried: parse_date(&last_tried)?,
+            })
+        })
+        .collect()
+    }
+
+    fn remove_error(&self, group: &str, article_number: usize) -> io::Result<()> {
+        self.transaction(|transaction| {
+            transaction.execute(
+                "DELETE FROM errors WHERE group_name = ?1 AND article_number = ?2",
+                params![group, article_number as i64],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn list_articles(&self, group: &str) -> io::Result<Vec<usize>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT article_number FROM articles WHERE group_name = ?1 ORDER BY article_number",
+            )
+            .map_err(io::Error::other)?;
+        statement
+            .query_map(params![group], |row| row.get::<_, i64>(0))
+            .map_err(io::Error::other)?
+            .map(|number| number.map(|n| n as usize).map_err(io::Error::other))
+            .collect()
+    }
+
+    fn list_groups(&self) -> io::Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT group_name FROM articles UNION SELECT group_name FROM read_status
+                ORDER BY group_name",
+            )
+            .map_err(io::Error::other)?;
+        statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(io::Error::other)?
+            .map(|group| group.map_err(io::Error::other))
+            .collect()
+    }
+
+    fn put_article_and_read_status(
+        &self,
+        group: &str,
+        article_number: usize,
+        raw_article: Vec<String>,
+        read_status: &ReadStatus,
+    ) -> io::Result<()> {
+        self.transaction(|transaction| {
+            insert_article(transaction, group, article_number, &raw_article)?;
+            update_read_status(transaction, group, read_status)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_str().unwrap();
+        let storage = SqliteStorage::open(base).unwrap();
+        let article = vec![
+            "From: John Doe <j.doe@example.org>\r\n".to_string(),
+            "Subject: a\r\n".to_string(),
+            "  folded\r\n".to_string(),
+            "Date: Thu, 1 Jan 2026 10:00:00 +0000\r\n".to_string(),
+            "Message-ID: <a@example.org>\r\n".to_string(),
+            "\r\n".to_string(),
+            "body\r\n".to_string(),
+        ];
+
+        assert_eq!(storage.get_read_status("g").unwrap().last_email, 0);
+        storage
+            .put_article_and_read_status("g", 3, article.clone(), &ReadStatus { last_email: 3 })
+            .unwrap();
+        storage.put_article("g", 1, article.clone()).unwrap();
+        storage.record_error("g", 2, "article unavailable").unwrap();
+
+        // reopened
+        let storage = SqliteStorage::open(base).unwrap();
+        assert_eq!(storage.get_read_status("g").unwrap().last_email, 3);
+        assert_eq!(storage.list_articles("g").unwrap(), vec![1, 3]);
+        let errors = storage.list_errors("g").unwrap();
+        assert_eq!((errors[0].article_number, errors[0].attempts), (2, 1));
+        assert_eq!(storage.list_groups().unwrap(), vec!["g".to_string()]);
+        assert_eq!(storage.get_article("g", 3).unwrap(), Some(article));
+        assert_eq!(storage.get_article("g", 2).unwrap(), None);
+
+        let connection = storage.connection.lock().unwrap();
+        let (subject, date_unix): (String, i64) = connection
+            .query_row(
+                "SELECT subject, date_unix FROM articles WHERE message_id = '<a@example.org>' AND article_number = 3",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(subject, "a folded");
+        assert_eq!(date_unix, 1767261600);
+        drop(connection);
+
+        storage.record_error("g", 2, "article unavailable").unwrap();
+        assert_eq!(storage.list_errors("g").unwrap()[0].attempts, 2);
+        storage.remove_error("g", 2).unwrap();
+        assert!(storage.list_errors("g").unwrap().is_empty());
+    }
+}
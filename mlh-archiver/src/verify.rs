@@ -0,0 +1,282 @@
+//! Integrity checks of an archive, for the `verify` subcommand.
+//!
+//! Each group is checked on its own: the read status against the stored articles and
+//! the recorded errors, and every stored article for a complete header section and
+//! last line. The article numbers listed by the server can be added to find the
+//! articles it has that the archive lacks.
+
+use crate::range_inputs::format_sequence;
+use crate::storage::{ArticleError, Storage};
+use std::{collections::HashSet, fmt, io};
+
+/// What was found in one group
+#[derive(Debug, serde::Serialize, PartialEq, Eq)]
+pub struct GroupReport {
+    pub group: String,
+    /// read status of the group
+    pub last_article_number: usize,
+    pub stored_articles: usize,
+    pub recorded_errors: usize,
+    /// article numbers up to `last_article_number` neither stored nor in the recorded errors
+    pub gaps: Vec<usize>,
+    /// stored articles that cannot be read, or were not completely written
+    pub damaged: Vec<DamagedArticle>,
+    /// present when compared with the server's article numbers
+    pub server: Option<ServerReport>,
+}
+
+#[derive(Debug, serde::Serialize, PartialEq, Eq)]
+pub struct DamagedArticle {
+    pub article_number: usize,
+    pub problem: String,
+}
+
+#[derive(Debug, serde::Serialize, PartialEq, Eq)]
+pub struct ServerReport {
+    /// number of articles listed by the server
+    pub articles: usize,
+    /// articles after `last_article_number`, not fetched yet
+    pub not_fetched: usize,
+}
+
+impl GroupReport {
+    pub fn has_problems(&self) -> bool {
+        !self.gaps.is_empty() || !self.damaged.is_empty()
+    }
+}
+
+/// checks a group of the archive
+///
+/// Articles the server does not list are skipped without being recorded, so without
+/// `server_articles` (from LISTGROUP) some gaps may be articles that never existed.
+pub fn verify_group(
+    storage: &dyn Storage,
+    group: &str,
+    server_articles: Option<&[usize]>,
+) -> io::Result<GroupReport> {
+    let last_article_number = storage.get_read_status(group)?.last_email;
+    let stored = storage.list_articles(group)?;
+    let errors = storage.list_errors(group)?;
+
+    let gaps = find_gaps(&stored, &errors, last_article_number, server_articles);
+
+    let mut damaged = vec![];
+    for article_number in stored.iter().copied() {
+        let problem = match storage.get_article(group, article_number) {
+            Ok(Some(raw_article)) => check_article(&raw_article),
+            Ok(None) => Some(ArticleProblem::Unreadable(
+                "listed but not found".to_string(),
+            )),
+            Err(e) => Some(ArticleProblem::Unreadable(e.to_string())),
+        };
+        if let Some(problem) = problem {
+            damaged.push(DamagedArticle {
+                article_number,
+                problem: problem.to_string(),
+            });
+        }
+    }
+
+    Ok(GroupReport {
+        group: group.to_string(),
+        last_article_number,
+        stored_articles: stored.len(),
+        recorded_errors: errors.len(),
+        gaps,
+        damaged,
+        server: server_articles.map(|server_articles| ServerReport {
+            articles: server_articles.len(),
+            not_fetched: server_articles
+                .iter()
+                .filter(|n| **n > last_article_number)
+                .count(),
+        }),
+    })
+}
+
+/// article numbers up to the read status that are neither stored nor recorded as errors
+///
+/// With the numbers the server lists, only those are considered. Otherwise, every number
+/// from the first stored or recorded article is.
+pub fn find_gaps(
+    stored: &[usize],
+    errors: &[ArticleError],
+    last_article_number: usize,
+    server_articles: Option<&[usize]>,
+) -> Vec<usize> {
+    let known: HashSet<usize> = stored
+        .iter()
+        .copied()
+        .chain(errors.iter().map(|e| e.article_number))
+        .collect();
+    match server_articles {
+        Some(server_articles) => server_articles
+            .iter()
+            .copied()
+            .filter(|n| *n <= last_article_number && !known.contains(n))
+            .collect(),
+        None => match known.iter().min() {
+            Some(first) => (*first..=last_article_number)
+                .filter(|n| !known.contains(n))
+                .collect(),
+            None => vec![],
+        },
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArticleProblem {
+    Empty,
+    /// no empty line ends the headers
+    NoHeaderEnd,
+    /// the last line has no line ending
+    Truncated,
+    /// line (counted from 1) that is neither a header field nor a continuation
+    InvalidHeader(usize),
+    Unreadable(String),
+}
+
+impl fmt::Display for ArticleProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArticleProblem::Empty => write!(f, "empty"),
+            ArticleProblem::NoHeaderEnd => write!(f, "headers are not terminated"),
+            ArticleProblem::Truncated => write!(f, "truncated last line"),
+            ArticleProblem::InvalidHeader(line) => write!(f, "invalid header at line {line}"),
+            ArticleProblem::Unreadable(e) => write!(f, "unreadable: {e}"),
+        }
+    }
+}
+
+/// checks the raw lines of an article: header fields (RFC 5322), an empty line after
+/// them, and a line ending at the end
+pub fn check_article(raw_article: &[String]) -> Option<ArticleProblem> {
+    if raw_article.iter().all(|line| line.is_empty()) {
+        return Some(ArticleProblem::Empty);
+    }
+    if raw_article.last().is_some_and(|line| !line.ends_with('\n')) {
+        return Some(ArticleProblem::Truncated);
+    }
+
+    for (i, line) in raw_article.iter().enumerate() {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return None;
+        }
+        let is_continuation = i > 0 && line.starts_with([' ', '\t']);
+        let is_field = line.split_once(':').is_some_and(|(name, _)| {
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+        });
+        if !is_continuation && !is_field {
+            return Some(ArticleProblem::InvalidHeader(i + 1));
+        }
+    }
+    Some(ArticleProblem::NoHeaderEnd)
+}
+
+impl fmt::Display for GroupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} articles stored, {} errors recorded, last article number {}",
+            self.group, self.stored_articles, self.recorded_errors, self.last_article_number
+        )?;
+        if let Some(server) = &self.server {
+            write!(
+                f,
+                ", {} articles on the server ({} not fetched yet)",
+                server.articles, server.not_fetched
+            )?;
+        }
+        if !self.gaps.is_empty() {
+            write!(
+                f,
+                "\n  {} missing: {}",
+                self.gaps.len(),
+                format_sequence(&self.gaps)
+            )?;
+        }
+        for damaged in &self.damaged {
+            write!(f, "\n  {}: {}", damaged.article_number, damaged.problem)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, ReadStatus};
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_check_article() {
+        assert_eq!(check_article(&lines("Subject: x\r\n\r\nbody\r\n")), None);
+        assert_eq!(check_article(&lines("Subject: x\r\n folded\r\n\r\n")), None);
+        assert_eq!(check_article(&[]), Some(ArticleProblem::Empty));
+        assert_eq!(
+            check_article(&lines("Subject: x\r\n\r\nbo")),
+            Some(ArticleProblem::Truncated)
+        );
+        assert_eq!(
+            check_article(&lines("Subject: x\r\nFrom: a\r\n")),
+            Some(ArticleProblem::NoHeaderEnd)
+        );
+        assert_eq!(
+            check_article(&lines("Subject: x\r\nnot a header\r\n\r\n")),
+            Some(ArticleProblem::InvalidHeader(2))
+        );
+        assert_eq!(
+            check_article(&lines(" Subject: x\r\n\r\n")),
+            Some(ArticleProblem::InvalidHeader(1))
+        );
+    }
+
+    #[test]
+    fn test_verify_group() {
+        let storage = MemoryStorage::new();
+        let article = lines("Subject: x\r\n\r\nbody\r\n");
+        for n in [3, 4, 7] {
+            storage.put_article("g", n, article.clone()).unwrap();
+        }
+        storage
+            .put_article("g", 8, lines("Subject: x\r\n\r\nbo"))
+            .unwrap();
+        storage.record_error("g", 5, "unavailable").unwrap();
+        storage
+            .put_read_status("g", &ReadStatus { last_email: 10 })
+            .unwrap();
+
+        let report = verify_group(&storage, "g", None).unwrap();
+        assert_eq!(report.gaps, vec![6, 9, 10]);
+        assert_eq!(
+            report.damaged,
+            vec![DamagedArticle {
+                article_number: 8,
+                problem: "truncated last line".to_string()
+            }]
+        );
+        assert!(report.has_problems());
+        assert_eq!(
+            report.to_string(),
+            "g: 4 articles stored, 1 errors recorded, last article number 10\n  3 missing: 6,9-10\n  8: truncated last line"
+        );
+
+        // only what the server has is missing
+        let report = verify_group(&storage, "g", Some(&[1, 3, 4, 5, 7, 8, 10, 11, 12])).unwrap();
+        assert_eq!(report.gaps, vec![1, 10]);
+        assert_eq!(
+            report.server,
+            Some(ServerReport {
+                articles: 9,
+                not_fetched: 2
+            })
+        );
+
+        let report = verify_group(&storage, "empty", None).unwrap();
+        assert!(!report.has_problems());
+    }
+}
@@ -1,17 +1,56 @@
+use crate::config::{AuthMechanism, GroupConfig, StartFrom, TlsMode};
+use crate::connection::{ConnectionConfig, Connector, Session};
 use crate::errors;
-use crate::file_utils;
+use crate::nntp::{self, NNTPStream};
+use crate::overview;
+use crate::range_inputs;
+use crate::rate_limit::{self, Limits, RateLimiter};
+use crate::storage::{ReadStatus, Storage};
+use crate::verify;
+use chrono::{DateTime, TimeDelta, Utc};
 use log::{Level, log_enabled};
-use nntp::NNTPStream;
-use std::{fmt, path::Path, thread::sleep, time::Duration};
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-pub fn connect_to_nntp(address: String) -> nntp::Result<NNTPStream> {
-    let mut nntp_stream = match NNTPStream::connect(address) {
-        Ok(stream) => stream,
-        Err(e) => {
-            return Err(e);
-        }
+// number of articles listed by each OVER command
+const OVERVIEW_BATCH_SIZE: usize = 1000;
+// number of articles listed by each OVER command of the search for a start date
+const DATE_SEARCH_WINDOW: usize = 100;
+// upper bound for pipelined ARTICLE commands, to stay a polite client
+pub const MAX_PIPELINE_DEPTH: u8 = 16;
+/// how often a waiting worker checks if the archiver is stopping
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn connect_to_nntp(connection: &ConnectionConfig) -> crate::Result<NNTPStream> {
+    let mut nntp_stream = match connection.tls_mode {
+        TlsMode::None => NNTPStream::connect(connection.address())?,
+        TlsMode::Implicit => NNTPStream::connect_tls(
+            connection.address(),
+            &connection.hostname,
+            connection.tls_config()?,
+        )?,
+        TlsMode::Starttls => NNTPStream::connect(connection.address())?
+            .starttls(&connection.hostname, connection.tls_config()?)?,
     };
 
+    // authenticate before anything else, as servers may hide groups from anonymous users
+    if let Some(username) = &connection.username {
+        authenticate(&mut nntp_stream, connection, username)?;
+    }
+
+    return Ok(nntp_stream);
+}
+
+/// asks the server for its capabilities. Returns an empty list if the command fails
+pub fn read_capabilities(nntp_stream: &mut dyn Session) -> Vec<String> {
     match nntp_stream.capabilities() {
         Ok(lines) => {
             if log_enabled!(Level::Debug) {
@@ -20,80 +59,260 @@ pub fn connect_to_nntp(address: String) -> nntp::Result<NNTPStream> {
                     log::debug!("{}", line);
                 }
             }
+            lines
+        }
+        Err(e) => {
+            log::error!("Failed checking server capabilities: {}", e);
+            vec![]
         }
-        Err(e) => log::error!("Failed checking server capabilities: {}", e),
     }
-    return Ok(nntp_stream);
+}
+
+fn authenticate(
+    nntp_stream: &mut NNTPStream,
+    connection: &ConnectionConfig,
+    username: &str,
+) -> crate::Result<()> {
+    let password = connection
+        .password()
+        .map_err(|e| errors::Error::Authentication(format!("could not read the password: {e}")))?;
+
+    let result = match connection.auth_mechanism {
+        AuthMechanism::UserPass => nntp_stream.authinfo_user_pass(username, &password),
+        AuthMechanism::SaslPlain => nntp_stream.authinfo_sasl_plain(username, &password),
+    };
+    match result {
+        Ok(_) => {
+            log::debug!("authenticated as {username}");
+            Ok(())
+        }
+        // network errors are worth a retry, refused credentials are not
+        Err(e) if nntp::errors::check_network_error(&e) => Err(errors::Error::NNTP(e)),
+        Err(e) => Err(errors::Error::Authentication(format!(
+            "server refused credentials for {username}: {e}"
+        ))),
+    }
 }
 
 pub struct Worker {
     id: u8,
-    hostname: String,
-    nntp_stream: NNTPStream,
-    base_output_path: String,
+    connector: Arc<dyn Connector>,
+    // None until the first connection, see connect
+    nntp_stream: Option<Box<dyn Session>>,
+    // server advertised OVER, used to skip missing article numbers
+    supports_over: bool,
+    // number of ARTICLE commands kept in flight. 1 means no pipelining
+    pipeline_depth: usize,
+    // how long unavailable articles are tried again. None disables the retries
+    error_retry_max_age: Option<TimeDelta>,
+    storage: Arc<dyn Storage>,
+    // read status of the group being read. Articles fetched again never move it back
+    last_email: usize,
     needs_reconnection: bool,
-    receiver: crossbeam_channel::Receiver<String>,
+    receiver: crossbeam_channel::Receiver<GroupConfig>,
+    // set when the archiver is stopping: the current article is the last one
+    stopping: Arc<AtomicBool>,
+    // where the results of the tasks are sent, if anywhere
+    results: Option<crossbeam_channel::Sender<TaskReport>>,
+    // shared by the workers of the server
+    server_limits: Arc<RateLimiter>,
+    connection_limits: RateLimiter,
 }
 
 impl Worker {
     pub fn new(
         id: u8,
-        hostname: String,
-        port: u16,
-        base_output_path: String,
-        receiver: crossbeam_channel::Receiver<String>,
+        connector: Arc<dyn Connector>,
+        storage: Arc<dyn Storage>,
+        pipeline_depth: u8,
+        error_retry_max_age_days: u32,
+        receiver: crossbeam_channel::Receiver<GroupConfig>,
+        stopping: Arc<AtomicBool>,
     ) -> Worker {
-        let nntp_stream = connect_to_nntp(format!("{}:{}", hostname.clone(), port))
-            .expect("Worker should have connected to the server");
+        if pipeline_depth > MAX_PIPELINE_DEPTH {
+            log::warn!(
+                "W{id}: pipeline_depth {pipeline_depth} is above the limit, using {MAX_PIPELINE_DEPTH}"
+            );
+        }
+        let pipeline_depth = pipeline_depth.clamp(1, MAX_PIPELINE_DEPTH) as usize;
 
         Worker {
             id,
-            hostname,
-            base_output_path,
-            nntp_stream,
+            connector,
+            storage,
+            nntp_stream: None,
+            supports_over: false,
+            pipeline_depth,
+            error_retry_max_age: (error_retry_max_age_days > 0)
+                .then(|| TimeDelta::days(error_retry_max_age_days.into())),
+            last_email: 0,
             needs_reconnection: false,
             receiver,
+            stopping,
+            results: None,
+            server_limits: Arc::new(RateLimiter::unlimited()),
+            connection_limits: RateLimiter::unlimited(),
+        }
+    }
+
+    /// limits the rate of the server, shared with the other workers, and of this connection
+    pub fn with_rate_limits(
+        mut self,
+        server_limits: Arc<RateLimiter>,
+        connection_limits: Limits,
+    ) -> Worker {
+        self.server_limits = server_limits;
+        self.connection_limits = RateLimiter::new(connection_limits, Duration::ZERO);
+        self
+    }
+
+    /// waits for the server, and for both article rates, before an ARTICLE command
+    fn wait_for_article(&self) {
+        self.server_limits.wait_for_article();
+        self.connection_limits.wait_for_article();
+    }
+
+    /// opens a fresh session once the server accepts commands, so TLS is negotiated
+    /// again. A refusal makes every worker wait
+    pub fn connect(&mut self) -> crate::Result<()> {
+        self.server_limits.wait_for_server();
+        let mut nntp_stream = match self.connector.connect() {
+            Ok(nntp_stream) => nntp_stream,
+            Err(e) => {
+                if let errors::Error::NNTP(error) = &e {
+                    self.check_server_busy(error);
+                }
+                return Err(e);
+            }
+        };
+        self.supports_over = overview::supports_over(&read_capabilities(nntp_stream.as_mut()));
+        self.nntp_stream = Some(nntp_stream);
+        self.needs_reconnection = false;
+        return Ok(());
+    }
+
+    /// the session opened by connect
+    fn session(&mut self) -> nntp::Result<&mut dyn Session> {
+        match self.nntp_stream.as_deref_mut() {
+            Some(nntp_stream) => Ok(nntp_stream),
+            None => Err(io::Error::from(io::ErrorKind::NotConnected).into()),
+        }
+    }
+
+    /// after an error, makes every worker wait if the server is refusing work
+    fn check_server_busy(&self, error: &nntp::NNTPError) {
+        if rate_limit::is_server_busy(error) {
+            let wait = self.server_limits.server_busy();
+            log::warn!(
+                "W{}: server busy, every connection waits {}s: {error}",
+                self.id,
+                wait.as_secs()
+            );
+        }
+    }
+
+    /// sends the result of each task to the scheduler
+    pub fn with_results(mut self, results: crossbeam_channel::Sender<TaskReport>) -> Worker {
+        self.results = Some(results);
+        self
+    }
+
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// sleeps, waking up early when the archiver is stopping. Returns false if it did
+    fn pause(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.stopping() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
+            }
+            sleep(left.min(STOP_CHECK_INTERVAL));
+        }
+        return false;
+    }
+
+    /// sends QUIT, unless the connection is already broken
+    fn close(&mut self) {
+        if self.needs_reconnection {
+            return;
+        }
+        let Some(nntp_stream) = self.nntp_stream.as_mut() else {
+            return;
+        };
+        match nntp_stream.quit() {
+            Ok(_) => log::debug!("W{}: Connection closed successfully", self.id),
+            Err(err) => log::warn!("W{}: Failed when closing connection: {err}", self.id),
         }
     }
 
     pub fn run(&mut self) -> crate::Result<()> {
         log::info!("W{}: started consumming tasks", self.id);
         loop {
+            if self.stopping() {
+                log::info!("W{}: stopping", self.id);
+                self.close();
+                return Ok(());
+            }
             // check if reconnection is needed before trying to connect
             if self.needs_reconnection {
                 log::debug!("W{}: will attempt a reconnection soon", self.id);
                 // wait  a minute before trying to reconnect
-                std::thread::sleep(Duration::from_secs(60));
-
-                log::info!("W{}: will attempt a reconnection", self.id);
-                match self.nntp_stream.re_connect() {
-                    Ok(_) => self.needs_reconnection = false,
-                    Err(e) => {
-                        log::error!(
-                            "W{}: attempted reconnection and failed with error {e}",
-                            self.id
-                        );
-                        return Err(errors::Error::NNTP(e));
-                    }
+                if !self.pause(Duration::from_secs(60)) {
+                    continue;
                 }
+                log::info!("W{}: will attempt a reconnection", self.id);
+            }
+            if (self.needs_reconnection || self.nntp_stream.is_none())
+                && let Err(e) = self.connect()
+            {
+                log::error!("W{}: failed connecting with error {e}", self.id);
+                // the next attempt waits, as above
+                self.needs_reconnection = true;
+                return Err(e);
             }
 
             log::info!("W{}: Reading new group from channel", self.id);
-            let group_name = self.receiver.recv().unwrap();
+            // the channel is closed when the archiver stops
+            let Ok(group) = self.receiver.recv() else {
+                log::info!("W{}: no more tasks, stopping", self.id);
+                self.close();
+                return Ok(());
+            };
+            let group_name = group.name.clone();
+            let started = Instant::now();
             // let handler_result =
-            match self.handle_group(group_name.clone()) {
+            match self.handle_task(&group) {
                 Ok(return_status) => {
                     log::info!("W{}: completed a task with: {return_status}", self.id);
+                    self.server_limits.server_ok();
+                    self.report(return_status, started);
+                }
+                Err(err) if self.stopping() => {
+                    log::warn!("W{}: stopped while reading {group_name}: {err}", self.id);
+                    self.needs_reconnection = nntp::errors::check_network_error(&err);
+                    continue;
                 }
                 Err(err) => {
-                    if nntp::errors::check_network_error(&err) {
+                    self.check_server_busy(&err);
+                    let kind = match nntp::errors::check_network_error(&err) {
+                        true => FailureKind::Network,
+                        false => FailureKind::Server,
+                    };
+                    self.report(
+                        WorkerGroupResult::Failed(group_name.clone(), kind, err.to_string()),
+                        started,
+                    );
+                    if kind == FailureKind::Network {
                         log::warn!(
                             "W{}: failed with a network error while reading {group_name}. Error {}",
                             self.id,
                             &err
                         );
                         // if connection error was returned, sleep a bit
-                        std::thread::sleep(Duration::from_secs(10));
+                        self.pause(Duration::from_secs(10));
                     } else {
                         log::error!(
                             "W{}: failed while processing {group_name} with error {}",
@@ -105,7 +324,7 @@ impl Worker {
                     // when an error happens, force a reconnection
                     self.needs_reconnection = true;
                     // attempt to close connection
-                    match self.nntp_stream.quit() {
+                    match self.session().and_then(|session| session.quit()) {
                         Ok(_) => {
                             log::debug!("W{}: Connection closed successfully", self.id);
                         }
@@ -114,67 +333,64 @@ impl Worker {
                                 "W{}: Failed when closing connection with error {err}. Waiting before triggering a reconnection",
                                 self.id
                             );
-                            std::thread::sleep(Duration::from_secs(5));
+                            self.pause(Duration::from_secs(5));
                         }
                     }
                 }
             };
             // interval between tasks
-            std::thread::sleep(Duration::from_secs(1));
+            self.pause(Duration::from_secs(1));
         }
     }
 
-    pub fn handle_group(&mut self, group_name: String) -> nntp::Result<WorkerGroupResult> {
-        let read_status: ReadStatus = match file_utils::read_yaml::<ReadStatus>(
-            format!(
-                "{}/{}/__last_article_number",
-                self.base_output_path, group_name
-            )
-            .as_str(),
-        ) {
-            Ok(r) => r,
-            Err(e) => {
-                log::warn!("W{}: Error reading status:  {e}", self.id);
-                // attempted to read a number from the file, or fallback to 1
-                let last_article_number = file_utils::try_read_number(Path::new(
-                    format!(
-                        "{}/{}/__last_article_number",
-                        self.base_output_path, group_name
-                    )
-                    .as_str(),
-                ))
-                .unwrap_or(0);
-                if last_article_number == 0 {
-                    log::info!("W{}: Reading list {group_name} from mail 0", self.id);
-                }
-
-                let read_status = ReadStatus {
-                    last_email: last_article_number,
-                };
-
-                // write ReadStatus
-                file_utils::write_yaml(
-                    format!(
-                        "{}/{}/__last_article_number",
-                        self.base_output_path, group_name
-                    )
-                    .as_str(),
-                    &read_status,
-                )?;
+    fn report(&self, result: WorkerGroupResult, started: Instant) {
+        if let Some(results) = &self.results {
+            let _ = results.send(TaskReport {
+                worker: self.id,
+                result,
+                duration: started.elapsed(),
+            });
+        }
+    }
 
-                read_status
-            }
+    /// reads the range of the group if it has one, or its new articles otherwise
+    pub fn handle_task(&mut self, group: &GroupConfig) -> nntp::Result<WorkerGroupResult> {
+        let Some(range) = &group.range else {
+            return self.handle_group(group);
         };
+        match range_inputs::parse_sequence(range) {
+            Ok(range) => {
+                let num_emails_read = self.handle_group_range(group.name.clone(), range)?;
+                Ok(WorkerGroupResult::Ok(group.name.clone(), num_emails_read))
+            }
+            Err(e) => {
+                log::error!("W{}: invalid range for {}: {e}", self.id, group.name);
+                Ok(WorkerGroupResult::NoNews(group.name.clone()))
+            }
+        }
+    }
+
+    pub fn handle_group(&mut self, group_config: &GroupConfig) -> nntp::Result<WorkerGroupResult> {
+        let group_name = group_config.name.clone();
+        let read_status = self.storage.get_read_status(&group_name)?;
+        if read_status.last_email == 0 {
+            log::info!("W{}: Reading list {group_name} from mail 0", self.id);
+        }
 
         let last_article_number = read_status.last_email;
+        self.last_email = last_article_number;
 
         log::info!(
             "W{}: Checking group : {group_name}. Local max ID: {last_article_number}",
             self.id
         );
 
-        match self.nntp_stream.group(&group_name) {
+        self.server_limits.wait_for_server();
+        match self.session()?.group(&group_name) {
             Ok(group) => {
+                self.recover_damaged_articles(&group_name)?;
+                self.retry_unavailable_articles(&group_name)?;
+
                 log::info!(
                     "W{}: Remote max for {} is {}, local is {}",
                     self.id,
@@ -183,20 +399,27 @@ impl Worker {
                     last_article_number
                 );
 
-                if last_article_number < group.high as usize {
+                // the last article read is stored already
+                let mut low = (last_article_number + 1).max(group.low as usize);
+                if let (0, Some(start_from)) = (last_article_number, &group_config.start_from) {
+                    low = low.max(self.start_article(
+                        &group_name,
+                        start_from,
+                        low,
+                        group.high as usize,
+                    )?);
+                }
+
+                if last_article_number < group.high as usize && low <= group.high as usize {
                     log::info!("W{}: Reading emails for group : {group_name}.", self.id);
                     // this call may return an IO error,
-                    match self.read_new_mails(
-                        group_name.clone(),
-                        last_article_number.max(group.low as usize),
-                        group.high as usize,
-                    ) {
+                    match self.read_new_mails(group_name.clone(), low, group.high as usize) {
                         Ok(num_emails_read) => {
                             return Ok(WorkerGroupResult::Ok(group_name, num_emails_read));
                         }
                         Err(e) => {
                             log::error!("W{}: Failed reading new mails: {e}", self.id);
-                            // TODO: return failure instead of error ?
+                            // reported to the scheduler as a failure
                             return Err(e);
                         }
                     };
@@ -219,32 +442,222 @@ impl Worker {
         // Ok(())
     }
 
+    /// first article to read in a group never read before, from its start_from setting
+    fn start_article(
+        &mut self,
+        group_name: &str,
+        start_from: &StartFrom,
+        low: usize,
+        high: usize,
+    ) -> nntp::Result<usize> {
+        let since = match start_from {
+            StartFrom::Article(article_number) => return Ok(*article_number),
+            StartFrom::Date(date) => date.and_time(chrono::NaiveTime::MIN).and_utc(),
+        };
+        if !self.supports_over {
+            log::warn!(
+                "W{}: the server has no OVER command, reading {group_name} from the start instead of {since}",
+                self.id
+            );
+            return Ok(low);
+        }
+        let first = self.first_article_since(since, low, high)?;
+        log::info!(
+            "W{}: reading {group_name} from article {first}, the first since {since}",
+            self.id
+        );
+        Ok(first)
+    }
+
+    /// bisects the group with OVER for the first article posted at or after `since`.
+    /// Returns high + 1 if there is none
+    ///
+    /// Articles are assumed to be numbered in date order, which is mostly true.
+    /// Windows without a readable date, or without articles (a gap in the numbering),
+    /// are treated as recent, so the search errs towards reading more articles.
+    fn first_article_since(
+        &mut self,
+        since: DateTime<Utc>,
+        low: usize,
+        high: usize,
+    ) -> nntp::Result<usize> {
+        let (mut first, mut last) = (low, high + 1);
+        while first < last {
+            let middle = first + (last - first) / 2;
+            let window_end = (middle + DATE_SEARCH_WINDOW - 1).min(high);
+            self.server_limits.wait_for_server();
+            let lines = match self.session()?.over(middle as isize, window_end as isize) {
+                Ok(lines) => lines,
+                // a gap in the numbering, like a window without a readable date
+                Err(e) if overview::no_articles_in_range(&e) => vec![],
+                Err(e) => return Err(e),
+            };
+            let oldest = overview::parse_overview(&lines)
+                .into_iter()
+                .find_map(|entry| Some((entry.article_number, entry.date?)));
+            match oldest {
+                Some((article_number, date)) if date < since => {
+                    first = (article_number + 1).min(last)
+                }
+                _ => last = middle,
+            }
+        }
+        Ok(first)
+    }
+
+    /// fetches again the articles the storage found incomplete (a write interrupted by a crash)
+    ///
+    /// The read status is not changed, as these articles were already read. Articles not
+    /// fetched again, when stopping or on an error, stay damaged for the next check.
+    fn recover_damaged_articles(&mut self, group_name: &str) -> nntp::Result<usize> {
+        let damaged = self.storage.damaged_articles(group_name)?;
+        if damaged.is_empty() {
+            return Ok(0);
+        }
+        log::warn!(
+            "W{}: {} damaged articles in {group_name}, fetching them again: {damaged:?}",
+            self.id,
+            damaged.len()
+        );
+
+        let mut recovered = 0;
+        for article_number in damaged {
+            if self.stopping() {
+                break;
+            }
+            match self.get_raw_article_by_number_retryable(article_number as isize, 3) {
+                Ok(raw_article) => {
+                    self.storage
+                        .put_article(group_name, article_number, raw_article)?;
+                    recovered += 1;
+                }
+                Err(e @ nntp::NNTPError::ArticleUnavailable) => {
+                    self.storage
+                        .record_error(group_name, article_number, &e.to_string())?;
+                    log::warn!(
+                        "W{}: damaged article {article_number} is not available anymore",
+                        self.id
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(recovered)
+    }
+
+    /// tries again the articles the server could not provide, once their backoff delay passed
+    ///
+    /// Fetched articles are removed from the errors. Articles first tried longer ago than
+    /// error_retry_max_age stay recorded, but are not tried anymore.
+    fn retry_unavailable_articles(&mut self, group_name: &str) -> nntp::Result<usize> {
+        let Some(max_age) = self.error_retry_max_age else {
+            return Ok(0);
+        };
+        let now = Utc::now();
+        let due: Vec<usize> = self
+            .storage
+            .list_errors(group_name)?
+            .into_iter()
+            .filter(|e| !e.expired(max_age, now) && e.next_try() <= now)
+            .map(|e| e.article_number)
+            .collect();
+        if due.is_empty() {
+            return Ok(0);
+        }
+        log::info!(
+            "W{}: trying again {} unavailable articles in {group_name}",
+            self.id,
+            due.len()
+        );
+
+        let mut recovered = 0;
+        for article_number in due {
+            if self.stopping() {
+                break;
+            }
+            // a single attempt, the backoff is between runs
+            match self.get_raw_article_by_number_retryable(article_number as isize, 0) {
+                Ok(raw_article) => {
+                    self.storage
+                        .put_article(group_name, article_number, raw_article)?;
+                    self.storage.remove_error(group_name, article_number)?;
+                    recovered += 1;
+                }
+                Err(e @ nntp::NNTPError::ArticleUnavailable) => {
+                    self.storage
+                        .record_error(group_name, article_number, &e.to_string())?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if recovered > 0 {
+            log::info!(
+                "W{}: {recovered} articles unavailable before were fetched in {group_name}",
+                self.id
+            );
+        }
+        Ok(recovered)
+    }
+
+    /// fetches the given articles of a group, returning how many were stored
     pub fn handle_group_range(
         &mut self,
         group_name: String,
         range: impl Iterator<Item = usize>,
-    ) -> nntp::Result<()> {
+    ) -> nntp::Result<usize> {
         log::info!("W{}: Checking group : {group_name}", self.id);
+        self.last_email = self.storage.get_read_status(&group_name)?.last_email;
 
-        match self.nntp_stream.group(&group_name) {
+        self.server_limits.wait_for_server();
+        match self.session()?.group(&group_name) {
             Ok(group) => {
                 log::info!(
                     "W{}: Will start collecting mails from range for group {group}",
                     self.id
                 );
-                for article_number in range {
-                    self.read_new_mails(group_name.clone(), article_number, article_number)?;
-                }
+                self.read_articles(&group_name, range, group.high as usize)
             }
             Err(e) => {
                 log::error!(
                     "W{}: failure connecting to {group_name}, error: {e}",
                     self.id
                 );
-                return Err(e);
+                Err(e)
             }
         }
-        Ok(())
+    }
+
+    /// fetches the articles listed by the server (LISTGROUP) that are missing up to the
+    /// read status: skipped by a crash, deleted by hand, or added by the server after
+    /// their number was read
+    ///
+    /// Articles recorded as unavailable are left to `retry_unavailable_articles`.
+    pub fn backfill_group(&mut self, group_name: String) -> nntp::Result<usize> {
+        let last_email = self.storage.get_read_status(&group_name)?.last_email;
+        self.server_limits.wait_for_server();
+        let server_articles: Vec<usize> = self
+            .session()?
+            .listgroup(&group_name)?
+            .into_iter()
+            .map(|n| n as usize)
+            .collect();
+        let missing = verify::find_gaps(
+            &self.storage.list_articles(&group_name)?,
+            &self.storage.list_errors(&group_name)?,
+            last_email,
+            Some(&server_articles),
+        );
+
+        log::info!(
+            "W{}: {group_name} has {} articles on the server, {} missing locally",
+            self.id,
+            server_articles.len(),
+            missing.len()
+        );
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        self.handle_group_range(group_name, missing.into_iter())
     }
 
     // read_new_mails checks for mails in an inclusive range between low and high
@@ -254,70 +667,211 @@ impl Worker {
         low: usize,
         high: usize,
     ) -> nntp::Result<usize> {
+        // single articles are fetched directly, an OVER would only add a round-trip
+        if self.supports_over && high > low {
+            return self.read_new_mails_with_overview(group_name, low, high);
+        }
+
         // take the last_article_number or the "low"" result for the group
+        return self.read_articles(&group_name, low..=high, high);
+    }
+
+    /// reads the range in batches, first listing existing articles with OVER,
+    /// so numbers missing in the server are not requested one by one.
+    fn read_new_mails_with_overview(
+        &mut self,
+        group_name: String,
+        low: usize,
+        high: usize,
+    ) -> nntp::Result<usize> {
         let mut num_emails_read: usize = 0;
-        for current_mail in low..=high {
-            match self.get_raw_article_by_number_retryable(current_mail as isize, 3) {
-                Ok(raw_article) => {
-                    file_utils::write_lines_file(
-                        Path::new(
-                            format!(
-                                "{}/{}/{}.eml",
-                                self.base_output_path, group_name, current_mail
-                            )
-                            .as_str(),
-                        ),
-                        raw_article,
-                    )
-                    .unwrap();
-                    num_emails_read += 1;
+        for batch_low in (low..=high).step_by(OVERVIEW_BATCH_SIZE) {
+            let batch_high = (batch_low + OVERVIEW_BATCH_SIZE - 1).min(high);
 
-                    // write ReadStatus
-                    file_utils::write_yaml(
-                        format!(
-                            "{}/{}/__last_article_number",
-                            self.base_output_path, group_name
-                        )
-                        .as_str(),
-                        &ReadStatus {
-                            last_email: current_mail,
-                        },
-                    )?;
-                }
-                Err(e) => {
-                    match e {
-                        nntp::NNTPError::ArticleUnavailable => {
-                            file_utils::append_line_to_file(
-                                Path::new(
-                                    format!("{}/{}/__errors", self.base_output_path, group_name)
-                                        .as_str(),
-                                ),
-                                format!("{current_mail},{e}").as_str(),
-                            )
-                            .unwrap();
-                            log::warn!(
-                                "W{}: Email with number {current_mail} unavailable",
-                                self.id
-                            );
-                        }
-                        _ => return Err(e),
-                    }
-                    // // TODO: should the program signal a need to reconnect here or upstream ?
-                    // return Err(e);
+            self.server_limits.wait_for_server();
+            let entries = match self
+                .session()?
+                .over(batch_low as isize, batch_high as isize)
+            {
+                Ok(lines) => overview::parse_overview(&lines),
+                // a gap in the numbering, nothing to read in this batch
+                Err(e) if overview::no_articles_in_range(&e) => vec![],
+                Err(e) if overview::over_unsupported(&e) => {
+                    log::warn!(
+                        "W{}: OVER failed for {group_name}, reading articles one by one: {e}",
+                        self.id
+                    );
+                    self.supports_over = false;
+                    num_emails_read += self.read_articles(&group_name, batch_low..=high, high)?;
+                    break;
                 }
-            }
+                Err(e) => return Err(e),
+            };
 
-            log::info!(
-                "W{}: {group_name} {}/{} ({:.2}%)",
+            log::debug!(
+                "W{}: {group_name} has {} articles ({} bytes) between {batch_low} and {batch_high}",
                 self.id,
-                current_mail,
-                high,
-                (current_mail as f64 / high as f64 * 100.0)
+                entries.len(),
+                entries.iter().map(|entry| entry.bytes).sum::<usize>()
             );
+
+            let numbers = entries
+                .iter()
+                .map(|entry| entry.article_number)
+                .filter(|number| (batch_low..=batch_high).contains(number));
+            num_emails_read += self.read_articles(&group_name, numbers, high)?;
+            if self.stopping() {
+                // the read status is the last article read
+                break;
+            }
+
+            // the whole batch was checked, even if its last numbers do not exist
+            self.last_email = self.last_email.max(batch_high);
+            self.storage.put_read_status(
+                &group_name,
+                &ReadStatus {
+                    last_email: self.last_email,
+                },
+            )?;
         }
         return Ok(num_emails_read);
     }
 
+    /// fetches and stores the articles in order, returning how many were stored
+    fn read_articles(
+        &mut self,
+        group_name: &str,
+        numbers: impl Iterator<Item = usize>,
+        high: usize,
+    ) -> nntp::Result<usize> {
+        if self.pipeline_depth > 1 {
+            return self.read_articles_pipelined(group_name, numbers, high);
+        }
+
+        let mut num_emails_read: usize = 0;
+        for current_mail in numbers {
+            if self.stopping() {
+                break;
+            }
+            let response = self.get_raw_article_by_number_retryable(current_mail as isize, 3);
+            if self.store_article(group_name, current_mail, response, high)? {
+                num_emails_read += 1;
+            }
+        }
+        Ok(num_emails_read)
+    }
+
+    /// keeps up to pipeline_depth ARTICLE commands in flight on the connection.
+    ///
+    /// Responses arrive in the order they were requested, so articles and the
+    /// read status are written in order, and the status never skips an article.
+    /// An article refused by the server is fetched again on its own, with the
+    /// usual retries, once the responses already requested are read. Network
+    /// errors and a busy server are returned so the connection gets replaced.
+    fn read_articles_pipelined(
+        &mut self,
+        group_name: &str,
+        mut numbers: impl Iterator<Item = usize>,
+        high: usize,
+    ) -> nntp::Result<usize> {
+        let mut num_emails_read: usize = 0;
+        let mut in_flight: VecDeque<usize> = VecDeque::with_capacity(self.pipeline_depth);
+        loop {
+            // fill the window, the articles already requested are still read when stopping
+            while in_flight.len() < self.pipeline_depth && !self.stopping() {
+                match numbers.next() {
+                    Some(article_number) => {
+                        self.wait_for_article();
+                        self.session()?
+                            .send_article_by_number(article_number as isize)?;
+                        in_flight.push_back(article_number);
+                    }
+                    None => break,
+                }
+            }
+
+            let Some(current_mail) = in_flight.pop_front() else {
+                break;
+            };
+            let response = self
+                .session()
+                .and_then(|session| session.read_raw_article());
+            let mut responses = vec![(current_mail, response)];
+            if let (_, Err(e)) = &responses[0]
+                && retryable_in_pipeline(e)
+            {
+                responses.extend(in_flight.drain(..).map(|article_number| {
+                    let response = self
+                        .session()
+                        .and_then(|session| session.read_raw_article());
+                    (article_number, response)
+                }));
+            }
+            for (article_number, response) in responses {
+                let response = match response {
+                    Err(e) if retryable_in_pipeline(&e) => {
+                        self.get_raw_article_by_number_retryable(article_number as isize, 3)
+                    }
+                    response => response,
+                };
+                if self.store_article(group_name, article_number, response, high)? {
+                    num_emails_read += 1;
+                }
+            }
+        }
+        Ok(num_emails_read)
+    }
+
+    /// stores the server response for one article, returning false if the server does not have it
+    fn store_article(
+        &mut self,
+        group_name: &str,
+        current_mail: usize,
+        response: nntp::Result<Vec<String>>,
+        high: usize,
+    ) -> nntp::Result<bool> {
+        let mut stored = false;
+        match response {
+            Ok(raw_article) => {
+                let bytes = raw_article.iter().map(|line| line.len()).sum();
+                // write the article and its ReadStatus
+                self.last_email = self.last_email.max(current_mail);
+                self.storage.put_article_and_read_status(
+                    group_name,
+                    current_mail,
+                    raw_article,
+                    &ReadStatus {
+                        last_email: self.last_email,
+                    },
+                )?;
+                stored = true;
+                self.server_limits.wait_for_bytes(bytes);
+                self.connection_limits.wait_for_bytes(bytes);
+            }
+            Err(e) => {
+                match e {
+                    nntp::NNTPError::ArticleUnavailable => {
+                        self.storage
+                            .record_error(group_name, current_mail, &e.to_string())?;
+                        log::warn!("W{}: Email with number {current_mail} unavailable", self.id);
+                    }
+                    _ => return Err(e),
+                }
+                // // TODO: should the program signal a need to reconnect here or upstream ?
+                // return Err(e);
+            }
+        }
+
+        log::info!(
+            "W{}: {group_name} {}/{} ({:.2}%)",
+            self.id,
+            current_mail,
+            high,
+            (current_mail as f64 / high as f64 * 100.0)
+        );
+        Ok(stored)
+    }
+
     fn get_raw_article_by_number_retryable(
         &mut self,
         mail_num: isize,
@@ -326,20 +880,19 @@ impl Worker {
         let mut attempts = 0;
         let retry_delay_ms = 600;
         loop {
-            match self.nntp_stream.raw_article_by_number(mail_num) {
+            self.wait_for_article();
+            match self
+                .session()
+                .and_then(|session| session.raw_article_by_number(mail_num))
+            {
                 Ok(raw_article) => {
                     return Ok(raw_article);
                 }
                 Err(e) => {
-                    log::warn!(
-                        "W{}: Failed reading article '{}' from '{}' : {}",
-                        self.id,
-                        mail_num,
-                        self.hostname,
-                        e
-                    );
+                    log::warn!("W{}: Failed reading article '{mail_num}': {e}", self.id);
                     attempts += 1;
-                    if attempts > max_retries {
+                    // retrying right away would not help, see check_server_busy
+                    if attempts > max_retries || rate_limit::is_server_busy(&e) {
                         // Return the last error after max retries
                         return Err(e);
                     }
@@ -348,7 +901,7 @@ impl Worker {
                         self.id,
                         (retry_delay_ms * (attempts + 1))
                     );
-                    sleep(Duration::from_millis(
+                    self.pause(Duration::from_millis(
                         (retry_delay_ms * (attempts + 1)) as u64,
                     ));
                 }
@@ -357,10 +910,45 @@ impl Worker {
     }
 }
 
+/// an error response to one pipelined ARTICLE command, the connection is still usable
+fn retryable_in_pipeline(error: &nntp::NNTPError) -> bool {
+    !matches!(error, nntp::NNTPError::ArticleUnavailable)
+        && !nntp::errors::check_network_error(error)
+        && !rate_limit::is_server_busy(error)
+}
+
+/// what a worker sends to the scheduler after each task
+#[derive(Debug, Clone)]
+pub struct TaskReport {
+    pub worker: u8,
+    pub result: WorkerGroupResult,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WorkerGroupResult {
     Ok(String, usize),
     NoNews(String),
-    // Failed(String),
+    /// group, kind of failure, and the error
+    Failed(String, FailureKind, String),
+}
+
+impl WorkerGroupResult {
+    pub fn group_name(&self) -> &str {
+        match self {
+            WorkerGroupResult::Ok(group_name, _)
+            | WorkerGroupResult::NoNews(group_name)
+            | WorkerGroupResult::Failed(group_name, _, _) => group_name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// the connection was lost, the worker reconnects
+    Network,
+    /// an error response from the server, or a local error
+    Server,
 }
 
 impl fmt::Display for WorkerGroupResult {
@@ -376,11 +964,599 @@ impl fmt::Display for WorkerGroupResult {
             WorkerGroupResult::NoNews(group_name) => {
                 write!(f, "No New e-mails from {:?}", group_name)
             }
+            WorkerGroupResult::Failed(group_name, kind, error) => {
+                write!(
+                    f,
+                    "Failed reading {:?} ({kind:?} error): {error}",
+                    group_name
+                )
+            }
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-struct ReadStatus {
-    pub last_email: usize,
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::nntp::{NNTPError, NewsGroup};
+    use crate::storage::{ArticleError, MemoryStorage};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        io,
+        sync::Mutex,
+    };
+
+    pub(crate) const GROUP: &str = "test.group";
+
+    /// one group of articles, and the commands the sessions received
+    #[derive(Default)]
+    pub(crate) struct FakeServer {
+        articles: BTreeMap<usize, Vec<String>>,
+        over: bool,
+        // answer to every OVER command, when it fails
+        over_error: Option<&'static str>,
+        // articles failing with a network error, this many more times
+        broken: HashMap<usize, u32>,
+        // articles refused by the server, this many more times
+        refused: HashMap<usize, u32>,
+        // stops the archiver once this article was sent
+        stop_after: Option<usize>,
+        // sessions refused as with too many connections, this many more times
+        busy: u32,
+        stopping: Arc<AtomicBool>,
+        commands: Vec<String>,
+        pub(crate) sessions: usize,
+    }
+
+    impl FakeServer {
+        pub(crate) fn new(numbers: impl IntoIterator<Item = usize>) -> FakeServer {
+            FakeServer {
+                articles: numbers
+                    .into_iter()
+                    .map(|number| (number, article(number)))
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        fn fetch(&mut self, article_number: isize) -> nntp::Result<Vec<String>> {
+            self.commands.push(format!("ARTICLE {article_number}"));
+            let article_number = article_number as usize;
+            if let Some(failures) = self.broken.get_mut(&article_number)
+                && *failures > 0
+            {
+                *failures -= 1;
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset).into());
+            }
+            if let Some(refusals) = self.refused.get_mut(&article_number)
+                && *refusals > 0
+            {
+                *refusals -= 1;
+                return Err(NNTPError::UnexpectedResponse(
+                    "403 archive temporarily unavailable".to_string(),
+                ));
+            }
+            if self.stop_after == Some(article_number) {
+                self.stopping.store(true, Ordering::SeqCst);
+            }
+            self.articles
+                .get(&article_number)
+                .cloned()
+                .ok_or(NNTPError::ArticleUnavailable)
+        }
+    }
+
+    fn article(article_number: usize) -> Vec<String> {
+        vec![
+            format!("Message-ID: <{article_number}@example.com>"),
+            String::new(),
+            format!("article {article_number}"),
+        ]
+    }
+
+    /// article n is posted n days after 2024-01-01
+    fn overview_line(article_number: usize) -> String {
+        let date = DateTime::from_timestamp(1_704_067_200 + article_number as i64 * 86_400, 0)
+            .unwrap()
+            .to_rfc2822();
+        format!("{article_number}\tsubject\tfrom\t{date}\t<{article_number}@example.com>\t\t100\t3")
+    }
+
+    struct FakeSession {
+        server: Arc<Mutex<FakeServer>>,
+        // pipelined ARTICLE commands
+        sent: VecDeque<isize>,
+    }
+
+    impl Session for FakeSession {
+        fn capabilities(&mut self) -> nntp::Result<Vec<String>> {
+            let mut capabilities = vec!["VERSION 2".to_string(), "READER".to_string()];
+            if self.server.lock().unwrap().over {
+                capabilities.push("OVER".to_string());
+            }
+            Ok(capabilities)
+        }
+
+        fn group(&mut self, group_name: &str) -> nntp::Result<NewsGroup> {
+            let mut server = self.server.lock().unwrap();
+            server.commands.push(format!("GROUP {group_name}"));
+            if group_name != GROUP {
+                return Err(NNTPError::NoSuchGroup);
+            }
+            Ok(NewsGroup {
+                name: group_name.to_string(),
+                low: server.articles.keys().next().map_or(1, |&n| n as isize),
+                high: server.articles.keys().last().map_or(0, |&n| n as isize),
+                status: "y".to_string(),
+            })
+        }
+
+        fn over(&mut self, low: isize, high: isize) -> nntp::Result<Vec<String>> {
+            let mut server = self.server.lock().unwrap();
+            server.commands.push(format!("OVER {low}-{high}"));
+            if let Some(error) = server.over_error {
+                return Err(NNTPError::UnexpectedResponse(error.to_string()));
+            }
+            let lines: Vec<String> = server
+                .articles
+                .range(low as usize..=high as usize)
+                .map(|(&article_number, _)| overview_line(article_number))
+                .collect();
+            match lines.is_empty() {
+                true => Err(NNTPError::UnexpectedResponse(
+                    "423 no articles in that range".to_string(),
+                )),
+                false => Ok(lines),
+            }
+        }
+
+        fn listgroup(&mut self, group_name: &str) -> nntp::Result<Vec<isize>> {
+            let mut server = self.server.lock().unwrap();
+            server.commands.push(format!("LISTGROUP {group_name}"));
+            Ok(server.articles.keys().map(|&n| n as isize).collect())
+        }
+
+        fn raw_article_by_number(&mut self, article_number: isize) -> nntp::Result<Vec<String>> {
+            self.server.lock().unwrap().fetch(article_number)
+        }
+
+        fn send_article_by_number(&mut self, article_number: isize) -> nntp::Result<()> {
+            self.sent.push_back(article_number);
+            Ok(())
+        }
+
+        fn read_raw_article(&mut self) -> nntp::Result<Vec<String>> {
+            let article_number = self.sent.pop_front().expect("an ARTICLE command was sent");
+            self.server.lock().unwrap().fetch(article_number)
+        }
+
+        fn quit(&mut self) -> nntp::Result<()> {
+            self.server
+                .lock()
+                .unwrap()
+                .commands
+                .push("QUIT".to_string());
+            Ok(())
+        }
+    }
+
+    pub(crate) struct FakeConnector(pub(crate) Arc<Mutex<FakeServer>>);
+
+    impl Connector for FakeConnector {
+        fn connect(&self) -> crate::Result<Box<dyn Session>> {
+            let mut server = self.0.lock().unwrap();
+            if server.busy > 0 {
+                server.busy -= 1;
+                return Err(NNTPError::UnexpectedResponse(
+                    "400 too many connections".to_string(),
+                ))?;
+            }
+            server.sessions += 1;
+            Ok(Box::new(FakeSession {
+                server: self.0.clone(),
+                sent: VecDeque::new(),
+            }))
+        }
+    }
+
+    struct Setup {
+        server: Arc<Mutex<FakeServer>>,
+        storage: Arc<MemoryStorage>,
+        tasks: crossbeam_channel::Sender<GroupConfig>,
+        stopping: Arc<AtomicBool>,
+    }
+
+    fn setup(server: FakeServer, pipeline_depth: u8) -> (Worker, Setup) {
+        let stopping = server.stopping.clone();
+        let server = Arc::new(Mutex::new(server));
+        let storage = Arc::new(MemoryStorage::new());
+        let (tasks, receiver) = crossbeam_channel::unbounded();
+        let mut worker = Worker::new(
+            0,
+            Arc::new(FakeConnector(server.clone())),
+            storage.clone(),
+            pipeline_depth,
+            30,
+            receiver,
+            stopping.clone(),
+        );
+        worker.connect().unwrap();
+        let setup = Setup {
+            server,
+            storage,
+            tasks,
+            stopping,
+        };
+        (worker, setup)
+    }
+
+    impl Setup {
+        fn last_email(&self) -> usize {
+            self.storage.get_read_status(GROUP).unwrap().last_email
+        }
+
+        fn commands(&self) -> Vec<String> {
+            std::mem::take(&mut self.server.lock().unwrap().commands)
+        }
+    }
+
+    #[test]
+    fn test_handle_group() {
+        let (mut worker, setup) = setup(FakeServer::new(1..=5), 1);
+        let group = GroupConfig::new(GROUP);
+
+        assert_eq!(
+            worker.handle_group(&group).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 5)
+        );
+        assert_eq!(
+            setup.storage.list_articles(GROUP).unwrap(),
+            vec![1, 2, 3, 4, 5]
+        );
+        assert_eq!(setup.storage.article(GROUP, 3), Some(article(3)));
+        assert_eq!(setup.last_email(), 5);
+
+        assert_eq!(
+            worker.handle_group(&group).unwrap(),
+            WorkerGroupResult::NoNews(GROUP.to_string())
+        );
+
+        // only the articles after the last one read are fetched
+        setup.commands();
+        setup.server.lock().unwrap().articles.insert(6, article(6));
+        assert_eq!(
+            worker.handle_group(&group).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 1)
+        );
+        assert_eq!(setup.commands(), vec!["GROUP test.group", "ARTICLE 6"]);
+        assert_eq!(setup.last_email(), 6);
+
+        assert!(matches!(
+            worker.handle_group(&GroupConfig::new("missing.group")),
+            Err(NNTPError::NoSuchGroup)
+        ));
+    }
+
+    #[test]
+    fn test_unavailable_articles_are_recorded() {
+        let (mut worker, setup) = setup(FakeServer::new([1, 2, 4]), 1);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 3)
+        );
+        let errors = setup.storage.list_errors(GROUP).unwrap();
+        assert_eq!(
+            errors.iter().map(|e| e.article_number).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(setup.last_email(), 4);
+    }
+
+    #[test]
+    fn test_articles_are_retried() {
+        let mut server = FakeServer::new(1..=3);
+        server.broken.insert(2, 2);
+        let (mut worker, setup) = setup(server, 1);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 3)
+        );
+        assert_eq!(
+            setup.commands(),
+            vec![
+                "GROUP test.group",
+                "ARTICLE 1",
+                "ARTICLE 2",
+                "ARTICLE 2",
+                "ARTICLE 2",
+                "ARTICLE 3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry_unavailable_articles() {
+        let (mut worker, setup) = setup(FakeServer::new(1..=3), 1);
+        setup
+            .storage
+            .put_read_status(GROUP, &ReadStatus { last_email: 3 })
+            .unwrap();
+        let now = Utc::now();
+        // due, not due yet, and expired
+        setup.storage.put_error(
+            GROUP,
+            ArticleError::new(1, "unavailable", now - TimeDelta::hours(2)),
+        );
+        setup
+            .storage
+            .put_error(GROUP, ArticleError::new(2, "unavailable", now));
+        setup.storage.put_error(
+            GROUP,
+            ArticleError::new(3, "unavailable", now - TimeDelta::days(31)),
+        );
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::NoNews(GROUP.to_string())
+        );
+        assert_eq!(setup.storage.list_articles(GROUP).unwrap(), vec![1]);
+        let errors = setup.storage.list_errors(GROUP).unwrap();
+        assert_eq!(
+            errors.iter().map(|e| e.article_number).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_run_reports_tasks() {
+        let (worker, setup) = setup(FakeServer::new(1..=2), 1);
+        let (results, reports) = crossbeam_channel::unbounded();
+        let mut worker = worker.with_results(results);
+
+        setup.tasks.send(GroupConfig::new(GROUP)).unwrap();
+        drop(setup.tasks);
+        worker.run().unwrap();
+
+        let report = reports.try_recv().unwrap();
+        assert_eq!(report.worker, 0);
+        assert_eq!(report.result, WorkerGroupResult::Ok(GROUP.to_string(), 2));
+        assert!(reports.try_recv().is_err());
+        assert_eq!(
+            setup.server.lock().unwrap().commands.last().unwrap(),
+            "QUIT"
+        );
+    }
+
+    #[test]
+    fn test_failure_is_reported_and_stops_while_waiting() {
+        let (worker, setup) = setup(FakeServer::new(1..=2), 1);
+        let (results, reports) = crossbeam_channel::unbounded();
+        let mut worker = worker.with_results(results);
+
+        setup.tasks.send(GroupConfig::new("missing.group")).unwrap();
+        let running = std::thread::spawn(move || worker.run());
+        let report = reports.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            report.result,
+            WorkerGroupResult::Failed(group, FailureKind::Server, _) if group == "missing.group"
+        ));
+
+        // the worker now waits a minute before reconnecting, stopping wakes it up
+        let stopped = Instant::now();
+        setup.stopping.store(true, Ordering::SeqCst);
+        running.join().unwrap().unwrap();
+        assert!(stopped.elapsed() < Duration::from_secs(5));
+        assert_eq!(setup.server.lock().unwrap().sessions, 1);
+    }
+
+    #[test]
+    fn test_server_busy_when_connecting() {
+        let (worker, setup) = setup(FakeServer::new(1..=2), 1);
+        let server_limits = Arc::new(RateLimiter::new(Limits::default(), Duration::from_secs(10)));
+        let mut worker = worker.with_rate_limits(server_limits.clone(), Limits::default());
+
+        setup.server.lock().unwrap().busy = 1;
+        let error = worker.connect().unwrap_err();
+        assert!(matches!(&error, errors::Error::NNTP(e) if rate_limit::is_server_busy(e)));
+        // every worker waits, and the next refusal doubles the wait
+        assert_eq!(server_limits.server_busy(), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn test_stop_after_current_article() {
+        let mut server = FakeServer::new(1..=5);
+        server.stop_after = Some(2);
+        let (mut worker, setup) = setup(server, 1);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 2)
+        );
+        assert_eq!(setup.last_email(), 2);
+
+        // stopped workers close their session without reading more tasks
+        setup.tasks.send(GroupConfig::new(GROUP)).unwrap();
+        worker.run().unwrap();
+        assert!(setup.stopping.load(Ordering::SeqCst));
+        assert_eq!(setup.commands().last().unwrap(), "QUIT");
+        assert_eq!(setup.last_email(), 2);
+    }
+
+    #[test]
+    fn test_read_with_overview() {
+        let mut server = FakeServer::new([1, 2, 2003, 2004]);
+        server.over = true;
+        let (mut worker, setup) = setup(server, 1);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 4)
+        );
+        // the batch without articles is skipped, not read one by one
+        assert_eq!(
+            setup.commands(),
+            vec![
+                "GROUP test.group",
+                "OVER 1-1000",
+                "ARTICLE 1",
+                "ARTICLE 2",
+                "OVER 1001-2000",
+                "OVER 2001-2004",
+                "ARTICLE 2003",
+                "ARTICLE 2004"
+            ]
+        );
+        assert_eq!(setup.last_email(), 2004);
+        assert!(setup.storage.list_errors(GROUP).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_without_overview() {
+        let mut server = FakeServer::new(1..=3);
+        server.over = true;
+        server.over_error = Some("503 overview not available");
+        let (mut worker, setup) = setup(server, 1);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 3)
+        );
+        assert_eq!(
+            setup.commands(),
+            vec![
+                "GROUP test.group",
+                "OVER 1-3",
+                "ARTICLE 1",
+                "ARTICLE 2",
+                "ARTICLE 3"
+            ]
+        );
+
+        // other errors fail the group
+        setup.server.lock().unwrap().over_error = Some("412 No newsgroup selected");
+        worker.supports_over = true;
+        setup
+            .storage
+            .put_read_status(GROUP, &ReadStatus { last_email: 0 })
+            .unwrap();
+        assert!(worker.handle_group(&GroupConfig::new(GROUP)).is_err());
+    }
+
+    #[test]
+    fn test_pipelined_articles() {
+        let mut server = FakeServer::new((1..=6).filter(|&n| n != 4));
+        server.refused.insert(3, 1);
+        let (mut worker, setup) = setup(server, 4);
+
+        assert_eq!(
+            worker.handle_group(&GroupConfig::new(GROUP)).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 5)
+        );
+        // the refused article is fetched again once the pipeline is empty
+        assert_eq!(
+            setup.commands(),
+            vec![
+                "GROUP test.group",
+                "ARTICLE 1",
+                "ARTICLE 2",
+                "ARTICLE 3",
+                "ARTICLE 4",
+                "ARTICLE 5",
+                "ARTICLE 6",
+                "ARTICLE 3"
+            ]
+        );
+        assert_eq!(
+            setup.storage.list_articles(GROUP).unwrap(),
+            vec![1, 2, 3, 5, 6]
+        );
+        let errors = setup.storage.list_errors(GROUP).unwrap();
+        assert_eq!(
+            errors.iter().map(|e| e.article_number).collect::<Vec<_>>(),
+            vec![4]
+        );
+        assert_eq!(setup.last_email(), 6);
+    }
+
+    #[test]
+    fn test_pipelined_network_error() {
+        let mut server = FakeServer::new(1..=6);
+        server.broken.insert(3, 1);
+        let (mut worker, setup) = setup(server, 4);
+
+        assert!(matches!(
+            worker.handle_group(&GroupConfig::new(GROUP)),
+            Err(NNTPError::Io(_))
+        ));
+        // the read status stops before the failed article
+        assert_eq!(setup.storage.list_articles(GROUP).unwrap(), vec![1, 2]);
+        assert_eq!(setup.last_email(), 2);
+    }
+
+    #[test]
+    fn test_start_from_date() {
+        // a gap in the numbering, bigger than the search window
+        let mut server = FakeServer::new((1..=500).filter(|n| !(200..=350).contains(n)));
+        server.over = true;
+        let (mut worker, setup) = setup(server, 1);
+        let since = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap() + TimeDelta::days(300);
+        let mut group = GroupConfig::new(GROUP);
+        group.start_from = Some(StartFrom::Date(since));
+
+        // article 300 would be the first, it is in the gap: the search ends right
+        // after the last article posted before
+        assert_eq!(
+            worker
+                .start_article(GROUP, &StartFrom::Date(since), 1, 500)
+                .unwrap(),
+            200
+        );
+        assert_eq!(
+            worker.handle_group(&group).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 150)
+        );
+        assert_eq!(setup.storage.list_articles(GROUP).unwrap()[0], 351);
+        assert_eq!(setup.last_email(), 500);
+    }
+
+    #[test]
+    fn test_start_from_date_bounds() {
+        let mut server = FakeServer::new(1..=500);
+        server.over = true;
+        let (mut worker, _) = setup(server, 1);
+        let since = |days| {
+            StartFrom::Date(
+                chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap() + TimeDelta::days(days),
+            )
+        };
+
+        // none since, or all of them
+        assert_eq!(
+            worker.start_article(GROUP, &since(1300), 1, 500).unwrap(),
+            501
+        );
+        assert_eq!(
+            worker.start_article(GROUP, &since(-700), 1, 500).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_start_from_article() {
+        let (mut worker, setup) = setup(FakeServer::new(1..=50), 1);
+        let mut group = GroupConfig::new(GROUP);
+        group.start_from = Some(StartFrom::Article(42));
+
+        assert_eq!(
+            worker.handle_group(&group).unwrap(),
+            WorkerGroupResult::Ok(GROUP.to_string(), 9)
+        );
+        assert_eq!(setup.storage.list_articles(GROUP).unwrap()[0], 42);
+
+        // without OVER, a date reads the whole group
+        let since = StartFrom::Date(chrono::NaiveDate::from_ymd_opt(2024, 2, 1).unwrap());
+        assert_eq!(worker.start_article(GROUP, &since, 1, 50).unwrap(), 1);
+    }
 }
@@ -2,11 +2,12 @@ use std::io;
 use std::path::Path;
 use std::{fs, thread, vec};
 use testcontainers::{
-    GenericBuildableImage, core::WaitFor, runners::SyncBuilder, runners::SyncRunner,
+    GenericBuildableImage, ImageExt, core::WaitFor, runners::SyncBuilder, runners::SyncRunner,
 };
 
-use mlh_archiver::config::AppConfig;
-use mlh_archiver::start;
+use mlh_archiver::config::{AppConfig, GroupEntry, TlsMode};
+use mlh_archiver::errors::Error;
+use mlh_archiver::{backfill, start};
 use walkdir::WalkDir;
 
 fn file_list_dir(path: String) -> Vec<String> {
@@ -55,10 +56,10 @@ fn test_read_from_local_nntp_server() {
         port: host_port,
         output_dir: output_dir.clone(),
         nthreads: 1,
-        group_lists: Some(vec!["ALL".to_owned()]),
+        group_lists: Some(vec![GroupEntry::Name("ALL".to_owned())]),
         // for the test, run all groups and then stop
         loop_groups: false,
-        article_range: None,
+        ..Default::default()
     };
 
     check_and_delete_folder(output_dir.clone()).unwrap();
@@ -68,7 +69,7 @@ fn test_read_from_local_nntp_server() {
     let child_handle = thread::spawn(move || {
         println!("Child thread started.");
         let result = start(&mut app_config);
-        assert!(result.is_ok());
+        assert!(matches!(result, Ok(true)));
 
         println!("Child thread stopped.");
     });
@@ -83,6 +84,8 @@ fn test_read_from_local_nntp_server() {
     // TODO: read file list dynamically from mock db file
     let mut expected_files = vec![
         "./test_output",
+        "./test_output/__message_index",
+        "./test_output/__schedule.yml",
         "./test_output/test.groups.foo",
         "./test_output/test.groups.foo/__last_article_number",
         "./test_output/test.groups.foo/1.eml",
@@ -97,3 +100,244 @@ fn test_read_from_local_nntp_server() {
 
     check_and_delete_folder(output_dir).unwrap();
 }
+
+#[test]
+fn test_read_from_local_nntps_server() {
+    println!("loading TLS Containerfile");
+    let image = GenericBuildableImage::new("test_nttps_server", "latest")
+        .with_dockerfile("./tests/tls/Containerfile")
+        .with_file("./tests/test_nttp_server", "./test_nttp_server")
+        .with_file("./tests/tls", "./tls")
+        .build_image()
+        .unwrap();
+
+    let container = image
+        .with_wait_for(WaitFor::message_on_stdout("Serving on port :8119"))
+        .start()
+        .unwrap();
+
+    // only the TLS terminator port is used
+    let host_port = container.get_host_port_ipv4(8563).unwrap();
+    let output_dir = "./test_output_tls".to_owned();
+
+    println!("TLS server container running on host port: {}", host_port);
+    let mut app_config = AppConfig {
+        hostname: Some("localhost".to_owned()),
+        port: host_port,
+        output_dir: output_dir.clone(),
+        nthreads: 1,
+        group_lists: Some(vec![GroupEntry::Name("test.groups.foo".to_owned())]),
+        loop_groups: false,
+        tls_mode: TlsMode::Implicit,
+        tls_ca_bundle: Some("./tests/tls/ca.pem".to_owned()),
+        ..Default::default()
+    };
+
+    check_and_delete_folder(output_dir.clone()).unwrap();
+
+    let child_handle = thread::spawn(move || {
+        let result = start(&mut app_config);
+        assert!(matches!(result, Ok(true)));
+    });
+
+    child_handle.join().expect("Child thread panicked");
+    container.stop().unwrap();
+    container.rm().unwrap();
+
+    let mut found_files = file_list_dir(output_dir.clone());
+    let mut expected_files = vec![
+        "./test_output_tls",
+        "./test_output_tls/__message_index",
+        "./test_output_tls/__schedule.yml",
+        "./test_output_tls/test.groups.foo",
+        "./test_output_tls/test.groups.foo/__last_article_number",
+        "./test_output_tls/test.groups.foo/1.eml",
+        "./test_output_tls/test.groups.foo/2.eml",
+    ];
+    found_files.sort();
+    expected_files.sort();
+    assert_eq!(found_files, expected_files);
+
+    check_and_delete_folder(output_dir).unwrap();
+}
+
+#[test]
+fn test_read_from_local_starttls_server() {
+    let image = GenericBuildableImage::new("test_nttps_server", "latest")
+        .with_dockerfile("./tests/tls/Containerfile")
+        .with_file("./tests/test_nttp_server", "./test_nttp_server")
+        .with_file("./tests/tls", "./tls")
+        .build_image()
+        .unwrap();
+
+    let container = image
+        .with_wait_for(WaitFor::message_on_stdout("Serving on port :8119"))
+        .start()
+        .unwrap();
+
+    // the STARTTLS front refuses everything but CAPABILITIES before the upgrade
+    let host_port = container.get_host_port_ipv4(8564).unwrap();
+    let output_dir = "./test_output_starttls".to_owned();
+
+    let mut app_config = AppConfig {
+        hostname: Some("localhost".to_owned()),
+        port: host_port,
+        output_dir: output_dir.clone(),
+        nthreads: 1,
+        group_lists: Some(vec![GroupEntry::Name("test.groups.foo".to_owned())]),
+        loop_groups: false,
+        tls_mode: TlsMode::Starttls,
+        tls_ca_bundle: Some("./tests/tls/ca.pem".to_owned()),
+        ..Default::default()
+    };
+
+    check_and_delete_folder(output_dir.clone()).unwrap();
+
+    let child_handle = thread::spawn(move || {
+        let result = start(&mut app_config);
+        assert!(matches!(result, Ok(true)));
+    });
+
+    child_handle.join().expect("Child thread panicked");
+    container.stop().unwrap();
+    container.rm().unwrap();
+
+    let mut found_files = file_list_dir(output_dir.clone());
+    let mut expected_files = vec![
+        "./test_output_starttls",
+        "./test_output_starttls/__message_index",
+        "./test_output_starttls/__schedule.yml",
+        "./test_output_starttls/test.groups.foo",
+        "./test_output_starttls/test.groups.foo/__last_article_number",
+        "./test_output_starttls/test.groups.foo/1.eml",
+        "./test_output_starttls/test.groups.foo/2.eml",
+    ];
+    found_files.sort();
+    expected_files.sort();
+    assert_eq!(found_files, expected_files);
+
+    check_and_delete_folder(output_dir).unwrap();
+}
+
+#[test]
+fn test_read_from_local_nntp_server_with_auth() {
+    let image = GenericBuildableImage::new("test_nttp_server", "latest")
+        .with_dockerfile("./tests/Containerfile")
+        .with_file("./tests/test_nttp_server", "./test_nttp_server")
+        .build_image()
+        .unwrap();
+
+    let container = image
+        .with_wait_for(WaitFor::message_on_stdout("Serving on port :8119"))
+        .with_env_var("REQUIRE_AUTH", "true")
+        .start()
+        .unwrap();
+
+    let host_port = container.get_host_port_ipv4(8119).unwrap();
+    let output_dir = "./test_output_auth".to_owned();
+
+    // credentials accepted by the mock server
+    let password_file = std::env::temp_dir().join("mlh_archiver_test_password");
+    fs::write(&password_file, "bar\n").unwrap();
+    let wrong_password_file = std::env::temp_dir().join("mlh_archiver_test_wrong_password");
+    fs::write(&wrong_password_file, "not-bar\n").unwrap();
+
+    let app_config = AppConfig {
+        hostname: Some("localhost".to_owned()),
+        port: host_port,
+        output_dir: output_dir.clone(),
+        nthreads: 1,
+        group_lists: Some(vec![GroupEntry::Name("test.groups.foo".to_owned())]),
+        loop_groups: false,
+        username: Some("foo".to_owned()),
+        password_file: Some(password_file.display().to_string()),
+        ..Default::default()
+    };
+
+    check_and_delete_folder(output_dir.clone()).unwrap();
+
+    // refused credentials must surface as an authentication error
+    let mut wrong_config = app_config.clone();
+    wrong_config.password_file = Some(wrong_password_file.display().to_string());
+    assert!(matches!(
+        start(&mut wrong_config),
+        Err(Error::Authentication(_))
+    ));
+
+    let mut app_config = app_config;
+    let child_handle = thread::spawn(move || {
+        let result = start(&mut app_config);
+        assert!(matches!(result, Ok(true)));
+    });
+
+    child_handle.join().expect("Child thread panicked");
+    container.stop().unwrap();
+    container.rm().unwrap();
+
+    let mut found_files = file_list_dir(output_dir.clone());
+    let mut expected_files = vec![
+        "./test_output_auth",
+        "./test_output_auth/__message_index",
+        "./test_output_auth/__schedule.yml",
+        "./test_output_auth/test.groups.foo",
+        "./test_output_auth/test.groups.foo/__last_article_number",
+        "./test_output_auth/test.groups.foo/1.eml",
+        "./test_output_auth/test.groups.foo/2.eml",
+    ];
+    found_files.sort();
+    expected_files.sort();
+    assert_eq!(found_files, expected_files);
+
+    check_and_delete_folder(output_dir).unwrap();
+    fs::remove_file(password_file).unwrap();
+    fs::remove_file(wrong_password_file).unwrap();
+}
+
+#[test]
+fn test_read_from_local_nntp_server_backfill() {
+    let image = GenericBuildableImage::new("test_nttp_server", "latest")
+        .with_dockerfile("./tests/Containerfile")
+        .with_file("./tests/test_nttp_server", "./test_nttp_server")
+        .build_image()
+        .unwrap();
+
+    let container = image
+        .with_wait_for(WaitFor::message_on_stdout("Serving on port :8119"))
+        .start()
+        .unwrap();
+
+    let host_port = container.get_host_port_ipv4(8119).unwrap();
+    let output_dir = "./test_output_backfill".to_owned();
+
+    let mut app_config = AppConfig {
+        hostname: Some("localhost".to_owned()),
+        port: host_port,
+        output_dir: output_dir.clone(),
+        nthreads: 1,
+        group_lists: Some(vec![GroupEntry::Name("test.groups.foo".to_owned())]),
+        loop_groups: false,
+        ..Default::default()
+    };
+
+    check_and_delete_folder(output_dir.clone()).unwrap();
+    // returns once every group was read
+    assert!(start(&mut app_config.clone()).unwrap());
+
+    // an article lost after it was read
+    let lost = format!("{output_dir}/test.groups.foo/1.eml");
+    let content = fs::read_to_string(&lost).unwrap();
+    fs::remove_file(&lost).unwrap();
+    let status_path = format!("{output_dir}/test.groups.foo/__last_article_number");
+    let status = fs::read_to_string(&status_path).unwrap();
+
+    backfill(&mut app_config).unwrap();
+
+    container.stop().unwrap();
+    container.rm().unwrap();
+
+    assert_eq!(fs::read_to_string(&lost).unwrap(), content);
+    // fetching an older article does not move the read status back
+    assert_eq!(fs::read_to_string(&status_path).unwrap(), status);
+
+    check_and_delete_folder(output_dir).unwrap();
+}
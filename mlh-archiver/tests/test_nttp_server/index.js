@@ -5,7 +5,10 @@ const mock_db      = require('./helpers').mock_db;
 const port = 8119;
 const addr = `nntp://0.0.0.0:${port}`;
 
-const nntp = new Server(addr, { requireAuth: false });
+// REQUIRE_AUTH=true makes the server refuse commands before AUTHINFO (user "foo", password "bar")
+const requireAuth = process.env.REQUIRE_AUTH === 'true';
+
+const nntp = new Server(addr, { requireAuth });
 
 console.log(`loading mock database`)
 mock_db(nntp, join(__dirname, './fixtures/db.yml'));
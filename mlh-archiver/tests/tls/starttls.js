@@ -0,0 +1,49 @@
+// STARTTLS front for the mock server: answers the commands allowed before TLS,
+// then upgrades the connection and forwards it to the plain text server
+const fs  = require('fs');
+const net = require('net');
+const tls = require('tls');
+
+const port = 8564;
+const secureContext = tls.createSecureContext({
+  cert: fs.readFileSync('/tls/server.pem'),
+  key: fs.readFileSync('/tls/server.key'),
+});
+
+function upgrade(client) {
+  const secure  = new tls.TLSSocket(client, { isServer: true, secureContext });
+  const backend = net.connect(8119, '127.0.0.1');
+  secure.on('error', () => backend.destroy());
+  backend.on('error', () => secure.destroy());
+  // the client was already greeted, the greeting of the server is dropped
+  backend.once('data', () => {
+    secure.pipe(backend);
+    backend.pipe(secure);
+  });
+}
+
+net.createServer((client) => {
+  let buffer = '';
+  const onData = (data) => {
+    buffer += data.toString('latin1');
+    let end;
+    while ((end = buffer.indexOf('\r\n')) >= 0) {
+      const command = buffer.slice(0, end).trim().toUpperCase();
+      buffer = buffer.slice(end + 2);
+      if (command === 'CAPABILITIES') {
+        client.write('101 Capability list:\r\nVERSION 2\r\nREADER\r\nSTARTTLS\r\n.\r\n');
+      } else if (command === 'STARTTLS') {
+        client.removeListener('data', onData);
+        client.write('382 Continue with TLS negotiation\r\n', () => upgrade(client));
+        return;
+      } else if (command === 'QUIT') {
+        client.end('205 Bye\r\n');
+      } else {
+        client.write('483 Encryption required\r\n');
+      }
+    }
+  };
+  client.on('error', () => client.destroy());
+  client.on('data', onData);
+  client.write('200 STARTTLS front ready\r\n');
+}).listen(port, () => console.log(`STARTTLS on port :${port}`));